@@ -0,0 +1,175 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::headless::Headless;
+use crate::hud::{HudAnchor, HudElement};
+use crate::localization::Localization;
+use crate::{Fonts, GameState, PlayTime};
+
+const ACHIEVEMENTS_PATH: &str = "achievements.json";
+
+/// Seconds an unlock notification stays on screen.
+const TOAST_SECONDS: f32 = 3.0;
+
+#[derive(Copy, Clone, Debug)]
+enum Goal {
+    WallsDug(u32),
+    WallsBuilt(u32),
+    /// Seconds played in a single game.
+    Survived(f32),
+    LevelsCleared(u32),
+}
+
+struct Achievement {
+    id: &'static str,
+    /// Localization key of the name shown when it unlocks.
+    name: &'static str,
+    goal: Goal,
+}
+
+const ACHIEVEMENTS: [Achievement; 4] = [
+    Achievement {
+        id: "dig_100",
+        name: "achievement.dig_100",
+        goal: Goal::WallsDug(100),
+    },
+    Achievement {
+        id: "build_50",
+        name: "achievement.build_50",
+        goal: Goal::WallsBuilt(50),
+    },
+    Achievement {
+        id: "survive_5_minutes",
+        name: "achievement.survive_5_minutes",
+        goal: Goal::Survived(300.0),
+    },
+    Achievement {
+        id: "clear_level",
+        name: "achievement.clear_level",
+        goal: Goal::LevelsCleared(1),
+    },
+];
+
+/// Progress across every game played, kept in `achievements.json` next to the
+/// game. Digs and builds made in practice mode don't count.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Achievements {
+    pub walls_dug: u32,
+    pub walls_built: u32,
+    pub levels_cleared: u32,
+    /// Ids of the achievements earned so far.
+    unlocked: Vec<String>,
+}
+
+impl Achievements {
+    /// A missing or unreadable file just means nothing has been earned yet.
+    fn load() -> Self {
+        fs::read_to_string(ACHIEVEMENTS_PATH)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string(self)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(ACHIEVEMENTS_PATH, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!(
+                "Could not save achievements to {}: {}",
+                ACHIEVEMENTS_PATH, e
+            );
+        }
+    }
+
+    fn reached(&self, goal: Goal, play_time: f32) -> bool {
+        match goal {
+            Goal::WallsDug(walls) => self.walls_dug >= walls,
+            Goal::WallsBuilt(walls) => self.walls_built >= walls,
+            Goal::Survived(seconds) => play_time >= seconds,
+            Goal::LevelsCleared(levels) => self.levels_cleared >= levels,
+        }
+    }
+}
+
+/// Unlock notification, removed when its timer runs out.
+struct Toast(Timer);
+
+fn check_achievements(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    localization: Res<Localization>,
+    play_time: Res<PlayTime>,
+    headless: Option<Res<Headless>>,
+    mut achievements: ResMut<Achievements>,
+) {
+    let mut unlocked = false;
+    for achievement in ACHIEVEMENTS.iter() {
+        if achievements.unlocked.iter().any(|id| id == achievement.id)
+            || !achievements.reached(achievement.goal, play_time.0)
+        {
+            continue;
+        }
+        achievements.unlocked.push(achievement.id.to_string());
+        unlocked = true;
+        let message = format!(
+            "{}: {}",
+            localization.t("achievement.unlocked"),
+            localization.t(achievement.name)
+        );
+        commands
+            .spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    message,
+                    TextStyle {
+                        font: fonts.ui.clone(),
+                        font_size: 16.0,
+                        color: Color::rgb(1.0, 0.85, 0.2),
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            })
+            .insert(Toast(Timer::from_seconds(TOAST_SECONDS, false)))
+            .insert(HudElement {
+                anchor: HudAnchor::BottomCenter,
+                order: 0,
+            });
+    }
+    if unlocked && headless.is_none() {
+        achievements.save();
+    }
+}
+
+/// Toasts count down in real time, so they clear even while paused.
+fn expire_toasts(mut commands: Commands, time: Res<Time>, mut toasts: Query<(Entity, &mut Toast)>) {
+    for (e, mut toast) in toasts.iter_mut() {
+        if toast.0.tick(time.delta()).finished() {
+            commands.entity(e).despawn();
+        }
+    }
+}
+
+/// Keeps the progress made in a game that unlocked nothing.
+fn save_achievements(achievements: Res<Achievements>, headless: Option<Res<Headless>>) {
+    if headless.is_none() {
+        achievements.save();
+    }
+}
+
+pub(crate) struct AchievementsPlugin;
+
+impl Plugin for AchievementsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Achievements::load())
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing).with_system(check_achievements.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Playing).with_system(save_achievements.system()),
+            )
+            .add_system(expire_toasts.system());
+    }
+}
@@ -0,0 +1,109 @@
+use bevy::prelude::*;
+
+use crate::enemy::{Enemy, EnemyHealth};
+use crate::grid::{entities_at, WorldGrid};
+use crate::{
+    open_interior_cells, ArenaConfig, Direction, Footprint, GameRng, GameState, Materials,
+    MoveAnimation, Player, Position, Size, SpawnClaims, Timesteps, WrapMode,
+};
+
+/// Boulders scattered over the arena at the start of each game.
+const BOULDER_COUNT: usize = 3;
+
+/// A rock too big to dig or carry. Walking into one pushes it a cell along,
+/// crushing any enemy underneath; pushing with the sprint key held sends it
+/// sliding.
+pub(crate) struct Boulder;
+
+/// A boulder still on the move after a sprinting push, one cell per action
+/// tick until something is in the way. There is no gravity, the arena being
+/// seen from above, so dug-out ground is crossed like any other open cell.
+pub(crate) struct Sliding {
+    pub(crate) direction: Direction,
+}
+
+fn spawn_boulders(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    materials: Res<Materials>,
+    mut rng: ResMut<GameRng>,
+    mut claims: ResMut<SpawnClaims>,
+) {
+    let mut open = open_interior_cells(&arena, &claims);
+    for _ in 0..BOULDER_COUNT {
+        if open.is_empty() {
+            break;
+        }
+        let index = rng.index(open.len());
+        let position = open.swap_remove(index);
+        claims.claim(position);
+        spawn_boulder(&mut commands, &materials, position);
+    }
+}
+
+pub(crate) fn spawn_boulder(commands: &mut Commands, materials: &Materials, position: Position) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.boulder_material.clone(),
+            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+            ..Default::default()
+        })
+        .insert(Boulder)
+        .insert(position)
+        .insert(Size::square(0.7));
+}
+
+/// Stops a sliding boulder at the first wall, boulder, player, pickup or edge
+/// ahead. An enemy in the way stops it too, and takes a hit.
+pub(crate) fn slide_system(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    wrap: Res<WrapMode>,
+    timesteps: Res<Timesteps>,
+    grid: Res<WorldGrid>,
+    players: Query<(&Position, &Footprint), (With<Player>, Without<Boulder>)>,
+    mut enemies: Query<&mut EnemyHealth, With<Enemy>>,
+    mut sliding: Query<(Entity, &Sliding, &mut Position), With<Boulder>>,
+) {
+    // Players have already moved this tick, so the grid may not have them yet
+    let mut claimed: Vec<Position> = players
+        .iter()
+        .flat_map(|(pos, footprint)| footprint.cells(pos))
+        .collect();
+    for (e, slide, mut pos) in sliding.iter_mut() {
+        let next = arena.step(&pos, slide.direction, &wrap);
+        let in_the_way = entities_at(&grid, &next);
+        if !arena.contains(&next) || claimed.contains(&next) || !in_the_way.is_empty() {
+            for enemy in in_the_way {
+                if let Ok(mut health) = enemies.get_mut(enemy) {
+                    health.0 = health.0.saturating_sub(1);
+                    if health.0 == 0 {
+                        commands.entity(enemy).despawn();
+                    }
+                }
+            }
+            commands.entity(e).remove::<Sliding>();
+            continue;
+        }
+        if next == pos.translated(slide.direction) {
+            commands.entity(e).insert(MoveAnimation::new(
+                *pos,
+                next,
+                timesteps.action_step() as f32,
+            ));
+        }
+        *pos = next;
+        claimed.push(next);
+    }
+}
+
+pub(crate) struct BoulderPlugin;
+
+impl Plugin for BoulderPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing)
+                .with_system(spawn_boulders.system().label("boulders").after("gems")),
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+use bevy::render::camera::{Camera, CameraProjection, OrthographicProjection};
+use rand::prelude::random;
+
+use crate::tween::{Easing, Tween};
+use crate::{has_area, tile_size, ArenaConfig, MainCamera, Player, ScalingMode};
+
+/// How the camera frames the arena, cycled with C. Fit shows the whole arena,
+/// Follow tracks the players and Free is a spectator view for inspecting
+/// large boards, panned with the arrow keys and zoomed with the mouse wheel.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum CameraMode {
+    Fit,
+    Follow,
+    Free,
+}
+
+impl Default for CameraMode {
+    fn default() -> Self {
+        CameraMode::Fit
+    }
+}
+
+impl CameraMode {
+    fn next(self) -> Self {
+        match self {
+            CameraMode::Fit => CameraMode::Follow,
+            CameraMode::Follow => CameraMode::Free,
+            CameraMode::Free => CameraMode::Fit,
+        }
+    }
+}
+
+/// Alternative to fitting the whole arena in the window: the camera tracks the
+/// players at a fixed zoom of `view` cells across. Enabled while the camera
+/// mode is Follow.
+pub struct CameraFollow {
+    pub enabled: bool,
+    pub view: u32,
+    /// How quickly the camera closes on its target, per second.
+    pub smoothing: f32,
+}
+
+impl Default for CameraFollow {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            view: 10,
+            smoothing: 5.0,
+        }
+    }
+}
+
+impl CameraFollow {
+    /// Cells visible along an axis of `cells`; arenas smaller than the view are
+    /// shown whole.
+    pub(crate) fn visible(&self, cells: u32) -> u32 {
+        if self.enabled {
+            cells.min(self.view)
+        } else {
+            cells
+        }
+    }
+}
+
+pub(crate) fn cycle_camera_mode(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut mode: ResMut<CameraMode>,
+    mut follow: ResMut<CameraFollow>,
+) {
+    if keyboard_input.just_pressed(KeyCode::C) {
+        *mode = mode.next();
+        follow.enabled = *mode == CameraMode::Follow;
+    }
+}
+
+/// Pixels per second the free camera pans at normal zoom.
+const PAN_SPEED: f32 = 400.0;
+/// Change in zoom per line scrolled.
+const ZOOM_STEP: f32 = 0.1;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.0;
+
+/// Bevy only rebuilds the projection on resize, so a new scale has to be
+/// applied by hand.
+fn set_zoom(projection: &mut OrthographicProjection, camera: &mut Camera, scale: f32) {
+    projection.scale = scale;
+    camera.projection_matrix = projection.get_projection_matrix();
+}
+
+/// Pans and zooms the camera in Free mode, leaving the players to whatever
+/// they were doing. Leaving Free mode restores the normal zoom.
+pub(crate) fn free_camera(
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mode: Res<CameraMode>,
+    mut wheel: EventReader<MouseWheel>,
+    mut cameras: Query<
+        (&mut Transform, &mut OrthographicProjection, &mut Camera),
+        With<MainCamera>,
+    >,
+) {
+    let scrolled: f32 = wheel.iter().map(|event| event.y).sum();
+    for (mut transform, mut projection, mut camera) in cameras.iter_mut() {
+        if *mode != CameraMode::Free {
+            if mode.is_changed() {
+                set_zoom(&mut projection, &mut camera, 1.);
+            }
+            continue;
+        }
+        let pan = [
+            (KeyCode::Left, -Vec2::X),
+            (KeyCode::Right, Vec2::X),
+            (KeyCode::Down, -Vec2::Y),
+            (KeyCode::Up, Vec2::Y),
+        ]
+        .iter()
+        .filter(|(key, _)| keyboard_input.pressed(*key))
+        .fold(Vec2::ZERO, |pan, (_, direction)| pan + *direction);
+        let step = pan * PAN_SPEED * projection.scale * time.delta_seconds();
+        transform.translation += step.extend(0.);
+        if scrolled != 0. {
+            // Scrolling up zooms in
+            let scale = (projection.scale * (1. - ZOOM_STEP * scrolled)).clamp(MIN_ZOOM, MAX_ZOOM);
+            set_zoom(&mut projection, &mut camera, scale);
+        }
+    }
+}
+
+/// Keeps `target` far enough from the arena edge that the window never shows
+/// past it.
+fn clamp_to_arena(target: f32, arena_extent: f32, half_window: f32) -> f32 {
+    let limit = (arena_extent - half_window).max(0.);
+    target.clamp(-limit, limit)
+}
+
+/// Eases the camera toward the middle of the players. Runs after
+/// `position_translation` so it chases this frame's positions.
+pub(crate) fn camera_follow(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    arena: Res<ArenaConfig>,
+    mode: Res<ScalingMode>,
+    follow: Res<CameraFollow>,
+    camera_mode: Res<CameraMode>,
+    players: Query<&Transform, (With<Player>, Without<MainCamera>)>,
+    mut cameras: Query<&mut Transform, With<MainCamera>>,
+) {
+    let window = windows.get_primary().unwrap();
+    if !has_area(window) || *camera_mode == CameraMode::Free {
+        return;
+    }
+    let mut camera = match cameras.iter_mut().next() {
+        Some(camera) => camera,
+        None => return,
+    };
+    if !follow.enabled {
+        camera.translation.x = 0.;
+        camera.translation.y = 0.;
+        return;
+    }
+    let (sum, count) = players
+        .iter()
+        .fold((Vec2::ZERO, 0), |(sum, count), transform| {
+            (sum + transform.translation.truncate(), count + 1)
+        });
+    if count == 0 {
+        return;
+    }
+    let centre = sum / count as f32;
+    let tile = tile_size(window, &arena, *mode, &follow);
+    let target = Vec2::new(
+        clamp_to_arena(
+            centre.x,
+            arena.width as f32 * tile.x / 2.,
+            window.width() / 2.,
+        ),
+        clamp_to_arena(
+            centre.y,
+            arena.height as f32 * tile.y / 2.,
+            window.height() / 2.,
+        ),
+    );
+    let t = (follow.smoothing * time.delta_seconds()).min(1.);
+    let current = camera.translation.truncate();
+    let next = current + (target - current) * t;
+    camera.translation.x = next.x;
+    camera.translation.y = next.y;
+}
+
+/// Seconds a shake lasts.
+const SHAKE_SECONDS: f32 = 0.15;
+
+/// Brief jolt of the camera when a wall breaks. `magnitude` is the largest
+/// offset in pixels, which `strength` eases off from.
+pub struct CameraShake {
+    pub magnitude: f32,
+    strength: Tween<f32>,
+    /// Offset applied last frame, taken back out before the camera follows.
+    offset: Vec2,
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        let mut strength = Tween::new(0., 0., SHAKE_SECONDS, Easing::EaseOut);
+        // Start finished so nothing shakes until the first wall breaks
+        strength.advance(SHAKE_SECONDS);
+        Self {
+            magnitude: 3.0,
+            strength,
+            offset: Vec2::ZERO,
+        }
+    }
+}
+
+impl CameraShake {
+    pub(crate) fn start(&mut self) {
+        self.strength = Tween::new(self.magnitude, 0., SHAKE_SECONDS, Easing::EaseOut);
+    }
+}
+
+/// Removes last frame's jitter so `camera_follow` works from the steady view.
+pub(crate) fn settle_camera_shake(
+    mut shake: ResMut<CameraShake>,
+    mut cameras: Query<&mut Transform, With<MainCamera>>,
+) {
+    let offset = std::mem::replace(&mut shake.offset, Vec2::ZERO);
+    for mut camera in cameras.iter_mut() {
+        camera.translation -= offset.extend(0.);
+    }
+}
+
+/// Jitters the camera around wherever `camera_follow` left it. Purely cosmetic
+/// and frame-rate dependent, so it stays off `GameRng`.
+pub(crate) fn apply_camera_shake(
+    time: Res<Time>,
+    mut shake: ResMut<CameraShake>,
+    mut cameras: Query<&mut Transform, With<MainCamera>>,
+) {
+    if shake.strength.finished() {
+        return;
+    }
+    let strength = shake.strength.advance(time.delta_seconds());
+    let offset = Vec2::new(random::<f32>() * 2. - 1., random::<f32>() * 2. - 1.) * strength;
+    for mut camera in cameras.iter_mut() {
+        camera.translation += offset.extend(0.);
+    }
+    shake.offset = offset;
+}
@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
+use bevy::utils::HashSet;
+
+use crate::enemy::Enemy;
+use crate::exit::Exit;
+use crate::gem::Gem;
+use crate::{has_area, MainCamera, Materials};
+
+/// Pixels kept between an arrow and the window edge.
+const COMPASS_MARGIN: f32 = 16.0;
+/// Drawn above the arena and the fog.
+const COMPASS_Z: f32 = 2.0;
+
+/// Points from the window edge toward `0`, an objective that's off screen.
+/// Kept off `Position` so the grid never sees it.
+struct CompassArrow(Entity);
+
+/// Keeps an arrow for every gem, enemy and exit, showing it only while its target
+/// is out of view. Runs after the camera has settled for the frame.
+fn render_compass(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    materials: Res<Materials>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<MainCamera>>,
+    gems: Query<(Entity, &Transform), With<Gem>>,
+    enemies: Query<(Entity, &Transform), With<Enemy>>,
+    exits: Query<(Entity, &Transform), With<Exit>>,
+    mut arrows: Query<
+        (Entity, &CompassArrow, &mut Transform, &mut Visible),
+        (
+            Without<Gem>,
+            Without<Enemy>,
+            Without<Exit>,
+            Without<MainCamera>,
+        ),
+    >,
+) {
+    let window = windows.get_primary().unwrap();
+    let (camera, projection) = match cameras.iter().next() {
+        Some(camera) => camera,
+        None => return,
+    };
+    let gem_targets = gems
+        .iter()
+        .map(|(e, _)| (e, materials.gem_material.clone()));
+    let enemy_targets = enemies
+        .iter()
+        .map(|(e, _)| (e, materials.enemy_material.clone()));
+    let exit_targets = exits
+        .iter()
+        .map(|(e, _)| (e, materials.exit_material.clone()));
+    let tracked: HashSet<Entity> = arrows.iter_mut().map(|(_, arrow, _, _)| arrow.0).collect();
+    for (target, material) in gem_targets.chain(enemy_targets).chain(exit_targets) {
+        if tracked.contains(&target) {
+            continue;
+        }
+        commands
+            .spawn_bundle(SpriteBundle {
+                material,
+                sprite: Sprite::new(Vec2::new(12.0, 4.0)),
+                visible: Visible {
+                    is_visible: false,
+                    is_transparent: true,
+                },
+                ..Default::default()
+            })
+            .insert(CompassArrow(target));
+    }
+    let half = Vec2::new(window.width(), window.height()) / 2. * projection.scale;
+    let inner = (half - Vec2::splat(COMPASS_MARGIN * projection.scale)).max(Vec2::ZERO);
+    let centre = camera.translation.truncate();
+    for (e, arrow, mut transform, mut visible) in arrows.iter_mut() {
+        let target = gems
+            .get(arrow.0)
+            .or_else(|_| enemies.get(arrow.0))
+            .or_else(|_| exits.get(arrow.0));
+        let target = match target {
+            Ok((_, target)) => target.translation.truncate() - centre,
+            Err(_) => {
+                commands.entity(e).despawn();
+                continue;
+            }
+        };
+        let off_screen = target.x.abs() > half.x || target.y.abs() > half.y;
+        let show = has_area(window) && off_screen;
+        if visible.is_visible != show {
+            visible.is_visible = show;
+        }
+        if !show {
+            continue;
+        }
+        // Slide along the line to the target until it meets the inset edge
+        let reach = (inner / target.abs()).min_element();
+        let edge = centre + target * reach;
+        transform.translation = edge.extend(COMPASS_Z);
+        transform.rotation = Quat::from_rotation_z(target.y.atan2(target.x));
+        transform.scale = Vec3::splat(projection.scale);
+    }
+}
+
+pub(crate) struct CompassPlugin;
+
+impl Plugin for CompassPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            render_compass.system().after("shake"),
+        );
+    }
+}
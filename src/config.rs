@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Deserializer};
+use std::fs;
+
+use crate::fog::FogOfWar;
+use crate::gravity::FallDamageConfig;
+use crate::oxygen::OxygenMode;
+use crate::trap::TrapEffect;
+use crate::water::WaterMode;
+use crate::{
+    ActionCooldowns, AnimatedDig, ArenaConfig, AttackByDig, AutoPlayAssist, BuildLimit, ChainDig,
+    MoveRepeat, MovementStyle, ParallaxConfig, PlayerAppearance, PlayerCount, PlayerSize,
+    PlayerSpawn, PracticeMode, RockCapacity, ScalingMode, TimedMode, Timesteps, TreasureWalls,
+    WallAppearance, WallDensity, WrapMode,
+};
+
+/// Optional overrides for the tunables below, next to the game.
+const CONFIG_PATH: &str = "config.toml";
+
+/// Every tunable that can be changed without recompiling. Each field becomes
+/// its own resource, and any key the file leaves out keeps its default.
+/// Key layouts live in `keybindings.json` instead.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    pub player_count: PlayerCount,
+    pub player_size: PlayerSize,
+    pub player_spawn: PlayerSpawn,
+    pub rock_capacity: RockCapacity,
+    pub cooldowns: ActionCooldowns,
+    pub build_limit: BuildLimit,
+    pub arena: ArenaConfig,
+    pub timesteps: Timesteps,
+    pub wall_density: WallDensity,
+    pub treasure: TreasureWalls,
+    pub move_repeat: MoveRepeat,
+    pub player_appearance: PlayerAppearance,
+    pub wall_appearance: WallAppearance,
+    pub fog: FogOfWar,
+    pub oxygen: OxygenMode,
+    pub water: WaterMode,
+    pub fall_damage: FallDamageConfig,
+    /// What the traps players lay do to enemies: `"stun"` or `"crush"`.
+    pub trap_effect: TrapEffect,
+    pub parallax: ParallaxConfig,
+    pub timed: TimedMode,
+    pub chain_dig: ChainDig,
+    pub auto_play_assist: AutoPlayAssist,
+    pub practice: PracticeMode,
+    pub wrap: WrapMode,
+    pub animated_dig: AnimatedDig,
+    pub attack_by_dig: AttackByDig,
+    /// `"step_stop"` or `"continuous"`.
+    pub movement_style: MovementStyle,
+    /// `"stretch"` or `"square"`.
+    pub scaling: ScalingMode,
+}
+
+impl GameConfig {
+    /// Reads `config.toml` if there is one, keeping the defaults when it is
+    /// missing or unreadable.
+    pub fn load() -> Self {
+        let contents = match fs::read_to_string(CONFIG_PATH) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+        let mut config: Self = toml::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Could not parse {}: {}", CONFIG_PATH, e);
+            Self::default()
+        });
+        config.arena = config.arena.validated();
+        config.timesteps = config.timesteps.validated();
+        config.move_repeat = config.move_repeat.validated();
+        config.cooldowns = config.cooldowns.validated();
+        config.timed = config.timed.validated();
+        config
+    }
+}
+
+/// `value`, or `default` with a warning when it isn't above zero: a step clock
+/// running at no rate, or a negative one, never stops catching up.
+pub(crate) fn checked_rate(name: &str, value: f64, default: f64) -> f64 {
+    if value.is_finite() && value > 0. {
+        return value;
+    }
+    warn!(
+        "{} of {} is not a finite rate above zero, using {}",
+        name, value, default
+    );
+    default
+}
+
+/// `value`, or `default` with a warning when it is negative, which a timer
+/// can't be made from.
+pub(crate) fn checked_seconds(name: &str, value: f32, default: f32) -> f32 {
+    if value.is_finite() && value >= 0. {
+        return value;
+    }
+    warn!(
+        "{} of {} seconds is negative, using {}",
+        name, value, default
+    );
+    default
+}
+
+/// Colours are written as `[red, green, blue]`.
+pub(crate) fn rgb<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let [red, green, blue] = <[f32; 3]>::deserialize(deserializer)?;
+    Ok(Color::rgb(red, green, blue))
+}
@@ -0,0 +1,104 @@
+use bevy::prelude::*;
+
+use crate::enemy::{EnemySettings, ENEMY_STEP};
+use crate::{Timesteps, WallDensity};
+
+/// Picked from the menu. Normal plays exactly as configured; the others scale
+/// wall spawning and the enemies up or down from there.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::Normal
+    }
+}
+
+impl Difficulty {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+
+    pub(crate) fn previous(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Hard,
+            Difficulty::Normal => Difficulty::Easy,
+            Difficulty::Hard => Difficulty::Normal,
+        }
+    }
+
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "difficulty.easy",
+            Difficulty::Normal => "difficulty.normal",
+            Difficulty::Hard => "difficulty.hard",
+        }
+    }
+
+    /// Multiplier on the configured wall spawn rate and density.
+    fn walls(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.5,
+        }
+    }
+
+    fn enemy_count(self) -> usize {
+        match self {
+            Difficulty::Easy => 2,
+            Difficulty::Normal => 3,
+            Difficulty::Hard => 5,
+        }
+    }
+
+    /// Multiplier on how often enemies step.
+    fn enemy_speed(self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.5,
+        }
+    }
+}
+
+/// The spawn rate and density from `config.toml`, kept so each game scales
+/// from them rather than from the last game's difficulty.
+pub(crate) struct DifficultyBase {
+    spawn_hz: f64,
+    pub(crate) wall_density: f32,
+}
+
+impl DifficultyBase {
+    pub(crate) fn new(timesteps: &Timesteps, density: &WallDensity) -> Self {
+        Self {
+            spawn_hz: timesteps.spawn_hz,
+            wall_density: density.target,
+        }
+    }
+}
+
+/// Harder games don't push the density past this, so there's room to move.
+const MAX_WALL_DENSITY: f32 = 0.8;
+
+pub(crate) fn apply_difficulty(
+    difficulty: Res<Difficulty>,
+    base: Res<DifficultyBase>,
+    mut timesteps: ResMut<Timesteps>,
+    mut density: ResMut<WallDensity>,
+    mut enemies: ResMut<EnemySettings>,
+) {
+    let walls = difficulty.walls();
+    timesteps.spawn_hz = base.spawn_hz * walls as f64;
+    density.target = (base.wall_density * walls).min(MAX_WALL_DENSITY.max(base.wall_density));
+    enemies.count = difficulty.enemy_count();
+    enemies.step = ENEMY_STEP / difficulty.enemy_speed();
+}
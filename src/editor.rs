@@ -0,0 +1,208 @@
+use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
+use std::fs;
+
+use crate::grid::{first_wall_at, WorldGrid};
+use crate::hud::{HudAnchor, HudElement};
+use crate::level::{Level, LEVEL_DIR};
+use crate::localization::Localization;
+use crate::{
+    cursor_cell, ArenaConfig, CameraFollow, Fonts, GameState, MainCamera, Materials, PlayerCount,
+    PlayerId, PlayerSpawn, Position, ScalingMode, Size, Wall,
+};
+
+/// Levels saved from the editor load with `CARNIVAL_LEVEL=custom`.
+const SAVED_LEVEL: &str = "custom";
+
+/// A wall placed in the editor. They only last until the editor closes.
+struct EditorWall;
+
+struct EditorHint;
+
+fn on_edge(arena: &ArenaConfig, pos: &Position) -> bool {
+    pos.x == 0 || pos.y == 0 || pos.x == arena.width as i32 - 1 || pos.y == arena.height as i32 - 1
+}
+
+fn spawn_editor_wall(
+    commands: &mut Commands,
+    materials: &Materials,
+    arena: &ArenaConfig,
+    pos: Position,
+) {
+    let material = if on_edge(arena, &pos) {
+        materials.boundary_material.clone()
+    } else {
+        materials.wall_material.clone()
+    };
+    commands
+        .spawn_bundle(SpriteBundle {
+            material,
+            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+            ..Default::default()
+        })
+        .insert(Wall)
+        .insert(EditorWall)
+        .insert(pos)
+        .insert(Size::square(0.8));
+}
+
+/// Starts from the loaded level, or an empty arena inside its boundary ring.
+fn spawn_editor(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    level: Option<Res<Level>>,
+    materials: Res<Materials>,
+    fonts: Res<Fonts>,
+    localization: Res<Localization>,
+) {
+    let walls: Vec<Position> = match &level {
+        Some(level) => level.walls.clone(),
+        None => (0..arena.width as i32)
+            .flat_map(|x| (0..arena.height as i32).map(move |y| Position { x, y }))
+            .filter(|p| on_edge(&arena, p))
+            .collect(),
+    };
+    for pos in walls {
+        spawn_editor_wall(&mut commands, &materials, &arena, pos);
+    }
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::with_section(
+                localization.t("editor.hint"),
+                TextStyle {
+                    font: fonts.ui.clone(),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(EditorHint)
+        .insert(HudElement {
+            anchor: HudAnchor::BottomCenter,
+            order: 0,
+        });
+}
+
+/// The editor's walls in the level text format, with player starts marked
+/// wherever they aren't walled over. A loaded level's budget carries over.
+fn layout_text(
+    arena: &ArenaConfig,
+    walls: &[Position],
+    starts: &[Position],
+    budget: Option<u32>,
+    exit: Option<(Position, bool)>,
+) -> String {
+    let mut text = budget.map_or(String::new(), |budget| format!("budget = {}\n", budget));
+    for y in (0..arena.height as i32).rev() {
+        for x in 0..arena.width as i32 {
+            let pos = Position { x, y };
+            text.push(if walls.contains(&pos) {
+                '#'
+            } else if starts.contains(&pos) {
+                'P'
+            } else if let Some((_, needs_gems)) = exit.filter(|(exit, _)| *exit == pos) {
+                if needs_gems {
+                    'e'
+                } else {
+                    'E'
+                }
+            } else {
+                '.'
+            });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn editor_input(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    arena: Res<ArenaConfig>,
+    mode: Res<ScalingMode>,
+    follow: Res<CameraFollow>,
+    // Paired up to stay within Bevy's limit on system parameters
+    (player_count, spawn): (Res<PlayerCount>, Res<PlayerSpawn>),
+    level: Option<Res<Level>>,
+    grid: Res<WorldGrid>,
+    materials: Res<Materials>,
+    localization: Res<Localization>,
+    mut state: ResMut<State<GameState>>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<MainCamera>>,
+    walls: Query<&Position, With<EditorWall>>,
+    mut hints: Query<&mut Text, With<EditorHint>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        state.set(GameState::Menu).unwrap();
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::X) {
+        let walls: Vec<Position> = walls.iter().copied().collect();
+        let starts: Vec<Position> = (0..player_count.0)
+            .map(|id| PlayerId(id).start(&arena, &spawn, level.as_deref()))
+            .collect();
+        let path = format!("{}/{}.txt", LEVEL_DIR, SAVED_LEVEL);
+        let message = match fs::write(
+            &path,
+            layout_text(
+                &arena,
+                &walls,
+                &starts,
+                level.as_ref().and_then(|l| l.budget),
+                level.as_ref().and_then(|l| l.exit),
+            ),
+        ) {
+            Ok(()) => format!("{} {}", localization.t("editor.saved"), path),
+            Err(e) => {
+                warn!("Could not save level to {}: {}", path, e);
+                e.to_string()
+            }
+        };
+        for mut text in hints.iter_mut() {
+            text.sections[0].value = format!("{}\n{}", localization.t("editor.hint"), message);
+        }
+    }
+    let window = windows.get_primary().unwrap();
+    let camera = match cameras.iter().next() {
+        Some(camera) => camera,
+        None => return,
+    };
+    let cell = match cursor_cell(window, camera, &arena, *mode, &follow) {
+        Some(cell) => cell,
+        None => return,
+    };
+    // Holding a button paints along the cursor's path
+    let wall = first_wall_at(&grid, &cell);
+    if mouse_input.pressed(MouseButton::Left) && wall.is_none() {
+        spawn_editor_wall(&mut commands, &materials, &arena, cell);
+    } else if mouse_input.pressed(MouseButton::Right) {
+        if let Some(wall) = wall {
+            commands.entity(wall).despawn();
+        }
+    }
+}
+
+fn despawn_editor(
+    mut commands: Commands,
+    entities: Query<Entity, Or<(With<EditorWall>, With<EditorHint>)>>,
+) {
+    for e in entities.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+pub(crate) struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Editor).with_system(spawn_editor.system()),
+        )
+        .add_system_set(SystemSet::on_update(GameState::Editor).with_system(editor_input.system()))
+        .add_system_set(SystemSet::on_exit(GameState::Editor).with_system(despawn_editor.system()));
+    }
+}
@@ -0,0 +1,362 @@
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::boulder::Boulder;
+use crate::grid::{first_wall_at, WorldGrid};
+use crate::level::Level;
+use crate::{
+    dig_wall, is_diggable, nearest_free_cell, open_interior_cells, run_if_simulating, Action,
+    AnimatedDig, ArenaConfig, Boundary, Digging, Direction, Footprint, GameRng, GameState, Lives,
+    Materials, Paused, Player, PlayerId, PlayerSpawn, Position, PracticeMode, Size, SpawnClaims,
+    StepClock, TimeScale, Toughness, Wall, WallType, WorldSteps,
+};
+
+/// Seconds between enemy steps at normal difficulty, well behind the player's
+/// action tick so a chase can be outrun.
+pub(crate) const ENEMY_STEP: f64 = 0.4;
+/// Enemies never spawn within this many steps of a player start.
+const SPAWN_CLEARANCE: i32 = 6;
+/// Seconds a respawned player can't be caught.
+const INVULNERABLE_SECONDS: f32 = 2.0;
+/// Seconds between blinks while invulnerable.
+const BLINK_SECONDS: f32 = 0.1;
+/// Seconds a thrown rock stops an enemy for.
+const STUN_SECONDS: f32 = 2.0;
+/// Digs it takes to finish off an enemy under `AttackByDig`.
+const ENEMY_HIT_POINTS: u32 = 2;
+
+pub(crate) struct Enemy {
+    pub(crate) kind: EnemyKind,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum EnemyKind {
+    /// Heads straight for the nearest player.
+    Chaser,
+    /// Steps in a random open direction.
+    Wanderer,
+    /// Chases like a `Chaser`, digging through walls in its way.
+    Digger,
+}
+
+/// Spawned in this order, starting over when a game has more enemies than kinds.
+const ENEMY_KINDS: [EnemyKind; 3] = [EnemyKind::Chaser, EnemyKind::Wanderer, EnemyKind::Digger];
+
+impl EnemyKind {
+    fn material(self, materials: &Materials) -> Handle<ColorMaterial> {
+        match self {
+            EnemyKind::Chaser => materials.enemy_material.clone(),
+            EnemyKind::Wanderer => materials.wanderer_material.clone(),
+            EnemyKind::Digger => materials.digger_material.clone(),
+        }
+    }
+}
+
+/// How many enemies a game starts with and how often they step, set from the
+/// difficulty.
+pub(crate) struct EnemySettings {
+    pub count: usize,
+    pub step: f64,
+}
+
+impl Default for EnemySettings {
+    fn default() -> Self {
+        Self {
+            count: ENEMY_KINDS.len(),
+            step: ENEMY_STEP,
+        }
+    }
+}
+
+/// Digs an enemy can still take under `AttackByDig` before it's gone.
+pub(crate) struct EnemyHealth(pub(crate) u32);
+
+/// Hit by a thrown rock: the enemy neither moves nor catches anyone until it
+/// wears off.
+pub(crate) struct Stunned(Timer);
+
+impl Stunned {
+    pub(crate) fn new() -> Self {
+        Self(Timer::from_seconds(STUN_SECONDS, false))
+    }
+}
+
+/// Grace period after a respawn, shown by blinking the sprite.
+struct Invulnerable(Timer);
+
+fn spawn_enemies(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    materials: Res<Materials>,
+    settings: Res<EnemySettings>,
+    practice: Res<PracticeMode>,
+    mut rng: ResMut<GameRng>,
+    mut claims: ResMut<SpawnClaims>,
+) {
+    if practice.0 {
+        return;
+    }
+    let mut open: Vec<Position> = open_interior_cells(&arena, &claims)
+        .into_iter()
+        .filter(|p| {
+            claims
+                .starts()
+                .iter()
+                .all(|s| (p.x - s.x).abs() + (p.y - s.y).abs() >= SPAWN_CLEARANCE)
+        })
+        .collect();
+    for kind in ENEMY_KINDS.iter().copied().cycle().take(settings.count) {
+        if open.is_empty() {
+            warn!("No open cell to spawn a {:?} in", kind);
+            return;
+        }
+        let index = rng.index(open.len());
+        let position = open.swap_remove(index);
+        claims.claim(position);
+        spawn_enemy(&mut commands, &materials, kind, position, ENEMY_HIT_POINTS);
+    }
+}
+
+pub(crate) fn spawn_enemy(
+    commands: &mut Commands,
+    materials: &Materials,
+    kind: EnemyKind,
+    position: Position,
+    hit_points: u32,
+) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: kind.material(materials),
+            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+            ..Default::default()
+        })
+        .insert(Enemy { kind })
+        .insert(EnemyHealth(hit_points))
+        .insert(position)
+        .insert(Size::square(0.6));
+}
+
+/// Greedy chase steps from `from` towards `to`: the axis with the larger gap
+/// first, then the other one as a fallback when the first is blocked.
+fn chase_steps(from: &Position, to: &Position) -> Vec<Direction> {
+    let (dx, dy) = (to.x - from.x, to.y - from.y);
+    let horizontal = match dx {
+        d if d > 0 => Some(Direction::Right),
+        d if d < 0 => Some(Direction::Left),
+        _ => None,
+    };
+    let vertical = match dy {
+        d if d > 0 => Some(Direction::Up),
+        d if d < 0 => Some(Direction::Down),
+        _ => None,
+    };
+    let steps = if dx.abs() >= dy.abs() {
+        [horizontal, vertical]
+    } else {
+        [vertical, horizontal]
+    };
+    steps.iter().flatten().copied().collect()
+}
+
+fn random_steps(rng: &mut GameRng) -> Vec<Direction> {
+    let mut steps = vec![
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+    let first = rng.index(steps.len());
+    steps.rotate_left(first);
+    steps
+}
+
+fn enemy_movement(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    grid: Res<WorldGrid>,
+    mut rng: ResMut<GameRng>,
+    animated: Res<AnimatedDig>,
+    crumbling: Query<(), With<Digging>>,
+    players: Query<&Position, (With<Player>, Without<Enemy>)>,
+    diggable: Query<Option<&WallType>, (With<Wall>, Without<Boundary>)>,
+    mut toughness: Query<&mut Toughness>,
+    boulders: Query<&Position, (With<Boulder>, Without<Enemy>)>,
+    mut enemies: Query<(&Enemy, &mut Position, Option<&Stunned>)>,
+) {
+    let crumbling = Some(&crumbling).filter(|_| animated.0);
+    // Enemies step one at a time, so later ones see where earlier ones went.
+    // Only a pushed boulder can land on an enemy, so boulders block them too
+    let mut claimed: Vec<Position> = enemies.iter_mut().map(|(_, p, _)| *p).collect();
+    claimed.extend(boulders.iter().copied());
+    for (enemy, mut pos, stunned) in enemies.iter_mut() {
+        if stunned.is_some() {
+            continue;
+        }
+        let nearest = players
+            .iter()
+            .min_by_key(|p| (p.x - pos.x).abs() + (p.y - pos.y).abs());
+        let steps = match (enemy.kind, nearest) {
+            (EnemyKind::Wanderer, _) => random_steps(&mut rng),
+            (_, Some(target)) => chase_steps(&pos, target),
+            (_, None) => continue,
+        };
+        for next in steps.into_iter().map(|direction| pos.translated(direction)) {
+            if !arena.contains(&next) || claimed.contains(&next) {
+                continue;
+            }
+            match first_wall_at(&grid, &next) {
+                None => {
+                    claimed.retain(|p| *p != *pos);
+                    claimed.push(next);
+                    *pos = next;
+                }
+                // Digging takes the whole step; the digger moves in next time
+                Some(wall) if enemy.kind == EnemyKind::Digger && is_diggable(&diggable, wall) => {
+                    dig_wall(&mut commands, &mut toughness, crumbling, wall);
+                }
+                Some(_) => continue,
+            }
+            break;
+        }
+    }
+}
+
+/// A caught player loses a shared life and respawns at their start; the game
+/// only ends once no lives remain.
+fn enemy_contact(
+    mut commands: Commands,
+    mut state: ResMut<State<GameState>>,
+    mut lives: ResMut<Lives>,
+    arena: Res<ArenaConfig>,
+    spawn: Res<PlayerSpawn>,
+    level: Option<Res<Level>>,
+    walls: Query<&Position, With<Wall>>,
+    mut players: Query<
+        (Entity, &PlayerId, &mut Position, &mut Player, &Footprint),
+        (Without<Enemy>, Without<Wall>, Without<Invulnerable>),
+    >,
+    enemies: Query<(&Position, Option<&Stunned>), With<Enemy>>,
+) {
+    for (entity, id, mut pos, mut player, footprint) in players.iter_mut() {
+        if !enemies
+            .iter()
+            .any(|(enemy, stunned)| footprint.covers(&pos, enemy) && stunned.is_none())
+        {
+            continue;
+        }
+        lives.0 = lives.0.saturating_sub(1);
+        if lives.0 == 0 {
+            state.overwrite_set(GameState::GameOver).unwrap();
+            return;
+        }
+        let occupied: Vec<Position> = walls
+            .iter()
+            .chain(enemies.iter().map(|(enemy, _)| enemy))
+            .copied()
+            .collect();
+        let start = id.start(&arena, &spawn, level.as_deref());
+        *pos = nearest_free_cell(&arena, start, &occupied, footprint).unwrap_or(start);
+        player.action = Action::Idle;
+        player.chaining = false;
+        commands
+            .entity(entity)
+            .insert(Invulnerable(Timer::from_seconds(
+                INVULNERABLE_SECONDS,
+                false,
+            )));
+    }
+}
+
+fn tick_invulnerability(
+    mut commands: Commands,
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut players: Query<(Entity, &mut Invulnerable, &mut Visible)>,
+) {
+    if paused.0 {
+        return;
+    }
+    for (entity, mut invulnerable, mut visible) in players.iter_mut() {
+        invulnerable.0.tick(scale.elapsed(&time));
+        if invulnerable.0.finished() {
+            visible.is_visible = true;
+            commands.entity(entity).remove::<Invulnerable>();
+        } else {
+            visible.is_visible = (invulnerable.0.elapsed_secs() / BLINK_SECONDS) as u32 % 2 == 0;
+        }
+    }
+}
+
+/// Enemies step on their own clock, scaled along with the player's actions.
+fn enemy_tick(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    settings: Res<EnemySettings>,
+    mut clock: Local<StepClock>,
+) -> ShouldRun {
+    clock.update(scale.delta(&time), settings.step)
+}
+
+/// Stuns wear off in simulated time, so slow motion keeps them as many enemy
+/// steps long.
+fn tick_stun(
+    mut commands: Commands,
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut enemies: Query<(Entity, &mut Stunned)>,
+) {
+    if paused.0 {
+        return;
+    }
+    for (entity, mut stunned) in enemies.iter_mut() {
+        if stunned.0.tick(scale.elapsed(&time)).finished() {
+            commands.entity(entity).remove::<Stunned>();
+        }
+    }
+}
+
+pub(crate) struct EnemyPlugin;
+
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<EnemySettings>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing).with_system(
+                    spawn_enemies
+                        .system()
+                        .label("enemies")
+                        .after("difficulty")
+                        .after("players"),
+                ),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        enemy_contact
+                            .system()
+                            .label(WorldSteps::Contact)
+                            .after(WorldSteps::Water),
+                    )
+                    .with_system(tick_invulnerability.system().before(WorldSteps::Contact))
+                    .with_system(
+                        tick_stun
+                            .system()
+                            .label(WorldSteps::Stun)
+                            .after(WorldSteps::Walls),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(enemy_tick.system().chain(run_if_simulating.system()))
+                    .with_system(
+                        enemy_movement
+                            .system()
+                            .label(WorldSteps::Enemies)
+                            .after(WorldSteps::Stun),
+                    ),
+            );
+    }
+}
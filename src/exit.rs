@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+
+use crate::gem::Gem;
+use crate::level::Level;
+use crate::{Footprint, GameState, Materials, Paused, Player, Position, Size, WorldSteps};
+
+/// A level's way out. Reaching it wins the level, but a locked exit stays
+/// shut until every gem has been collected.
+pub(crate) struct Exit {
+    needs_gems: bool,
+}
+
+fn spawn_exit(mut commands: Commands, level: Option<Res<Level>>, materials: Res<Materials>) {
+    let (position, needs_gems) = match level.as_ref().and_then(|l| l.exit) {
+        Some(exit) => exit,
+        None => return,
+    };
+    let material = if needs_gems {
+        materials.locked_exit_material.clone()
+    } else {
+        materials.exit_material.clone()
+    };
+    commands
+        .spawn_bundle(SpriteBundle {
+            material,
+            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+            ..Default::default()
+        })
+        .insert(Exit { needs_gems })
+        .insert(position)
+        .insert(Size::square(0.9));
+}
+
+/// Unlocks exits once the last gem is gone and wins the level when a player
+/// stands on an open one.
+fn check_exit(
+    paused: Res<Paused>,
+    materials: Res<Materials>,
+    mut state: ResMut<State<GameState>>,
+    gems: Query<(), With<Gem>>,
+    players: Query<(&Position, &Footprint), With<Player>>,
+    mut exits: Query<(&mut Exit, &Position, &mut Handle<ColorMaterial>)>,
+) {
+    if paused.0 {
+        return;
+    }
+    let gems_left = gems.iter().next().is_some();
+    for (mut exit, pos, mut material) in exits.iter_mut() {
+        if exit.needs_gems && !gems_left {
+            exit.needs_gems = false;
+            *material = materials.exit_material.clone();
+        }
+        if !exit.needs_gems
+            && players
+                .iter()
+                .any(|(player, footprint)| footprint.covers(player, pos))
+        {
+            state.overwrite_set(GameState::Win).unwrap();
+            return;
+        }
+    }
+}
+
+pub(crate) struct ExitPlugin;
+
+impl Plugin for ExitPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing).with_system(spawn_exit.system()),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(
+                check_exit
+                    .system()
+                    .label(WorldSteps::Exit)
+                    .after(WorldSteps::Trapped),
+            ),
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use serde::Deserialize;
+
+use crate::{
+    cell_to_world, has_area, tile_size, ArenaConfig, CameraFollow, GameState, Materials, Player,
+    Position, ScalingMode, Size,
+};
+
+/// Limited visibility: everything further than `sight_radius` cells from every
+/// player is covered over. Enabled from `config.toml`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FogOfWar {
+    pub enabled: bool,
+    pub sight_radius: f32,
+}
+
+impl Default for FogOfWar {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sight_radius: 4.0,
+        }
+    }
+}
+
+/// Cells some player has had in sight this game. They stay dimly visible.
+#[derive(Default)]
+pub(crate) struct Explored(HashSet<Position>);
+
+/// Covers one cell. Kept off `Position` so `position_translation` leaves its
+/// depth alone and it draws over whatever is underneath.
+pub(crate) struct FogCell(Position);
+
+fn spawn_fog(
+    mut commands: Commands,
+    fog: Res<FogOfWar>,
+    arena: Res<ArenaConfig>,
+    materials: Res<Materials>,
+    mut explored: ResMut<Explored>,
+) {
+    explored.0.clear();
+    if !fog.enabled {
+        return;
+    }
+    for x in 0..arena.width as i32 {
+        for y in 0..arena.height as i32 {
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: materials.fog_material.clone(),
+                    sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                    ..Default::default()
+                })
+                .insert(FogCell(Position { x, y }))
+                .insert(Size::square(1.0));
+        }
+    }
+}
+
+/// Lights cells within sight of the nearest player and dims the ones seen
+/// before. Materials are only swapped when a cell's state changes.
+fn update_fog(
+    windows: Res<Windows>,
+    arena: Res<ArenaConfig>,
+    mode: Res<ScalingMode>,
+    follow: Res<CameraFollow>,
+    fog: Res<FogOfWar>,
+    materials: Res<Materials>,
+    mut explored: ResMut<Explored>,
+    players: Query<&Position, With<Player>>,
+    mut cells: Query<(
+        &FogCell,
+        &mut Transform,
+        &mut Visible,
+        &mut Handle<ColorMaterial>,
+    )>,
+) {
+    let window = windows.get_primary().unwrap();
+    if !has_area(window) {
+        return;
+    }
+    let tile = tile_size(window, &arena, *mode, &follow);
+    let sight = fog.sight_radius * fog.sight_radius;
+    for (cell, mut transform, mut visible, mut material) in cells.iter_mut() {
+        let FogCell(pos) = cell;
+        let in_sight = players.iter().any(|player| {
+            let dx = (player.x - pos.x) as f32;
+            let dy = (player.y - pos.y) as f32;
+            dx * dx + dy * dy <= sight
+        });
+        if in_sight {
+            explored.0.insert(*pos);
+        }
+        if visible.is_visible == in_sight {
+            visible.is_visible = !in_sight;
+        }
+        if !in_sight && explored.0.contains(pos) && *material != materials.explored_material {
+            *material = materials.explored_material.clone();
+        }
+        let centre = cell_to_world(Vec2::new(pos.x as f32, pos.y as f32), tile, &arena);
+        transform.translation = centre.extend(1.0);
+    }
+}
+
+pub(crate) struct FogPlugin;
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Explored>()
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_fog.system()))
+            .add_system_to_stage(CoreStage::PostUpdate, update_fog.system());
+    }
+}
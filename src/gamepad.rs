@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::{steer, use_action, Action, Direction, MovementStyle, Paused, Player, PlayerId};
+
+/// Stick deflection below this is treated as drift.
+const STICK_DEADZONE: f32 = 0.5;
+const ACTION_BUTTON: GamepadButtonType = GamepadButtonType::South;
+const SPRINT_BUTTON: GamepadButtonType = GamepadButtonType::RightTrigger;
+
+/// Each player is driven by the gamepad with the same index.
+fn gamepad_for(id: &PlayerId) -> Gamepad {
+    Gamepad(id.0 as usize)
+}
+
+pub(crate) fn action_held(buttons: &Input<GamepadButton>, id: &PlayerId) -> bool {
+    buttons.pressed(GamepadButton(gamepad_for(id), ACTION_BUTTON))
+}
+
+/// The direction the left stick is pushed along its dominant axis, if any.
+fn stick_direction(axes: &Axis<GamepadAxis>, gamepad: Gamepad) -> Option<Direction> {
+    let x = axes.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX))?;
+    let y = axes.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickY))?;
+    if x.abs().max(y.abs()) < STICK_DEADZONE {
+        return None;
+    }
+    Some(if x.abs() > y.abs() {
+        if x > 0. {
+            Direction::Right
+        } else {
+            Direction::Left
+        }
+    } else if y > 0. {
+        Direction::Up
+    } else {
+        Direction::Down
+    })
+}
+
+/// Runs after keyboard input so either device can drive the same player. The
+/// stick steers once per push, like a key press, rather than every frame.
+pub(crate) fn gamepad_input(
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    paused: Res<Paused>,
+    style: Res<MovementStyle>,
+    mut sticks: Local<HashMap<PlayerId, Direction>>,
+    mut players: Query<(&PlayerId, &mut Player)>,
+) {
+    if paused.0 {
+        return;
+    }
+    for (id, mut p) in players.iter_mut() {
+        let gamepad = gamepad_for(id);
+        let pressed = |button| buttons.just_pressed(GamepadButton(gamepad, button));
+        let dpad = [
+            (GamepadButtonType::DPadUp, Direction::Up),
+            (GamepadButtonType::DPadDown, Direction::Down),
+            (GamepadButtonType::DPadLeft, Direction::Left),
+            (GamepadButtonType::DPadRight, Direction::Right),
+        ];
+        let mut steered = dpad
+            .iter()
+            .filter(|(button, _)| pressed(*button))
+            .map(|(_, direction)| *direction)
+            .last();
+        let stick = stick_direction(&axes, gamepad);
+        if stick.is_some() && stick != sticks.get(id).copied() {
+            steered = stick;
+        }
+        match stick {
+            Some(direction) => sticks.insert(*id, direction),
+            None => sticks.remove(id),
+        };
+        if let Some(direction) = steered {
+            p.assisted = false;
+            steer(&mut p, direction, *style);
+        }
+        if p.action == Action::Move && buttons.pressed(GamepadButton(gamepad, SPRINT_BUTTON)) {
+            p.sprinting = true;
+        }
+        if pressed(ACTION_BUTTON) {
+            p.assisted = false;
+            use_action(&mut p);
+            p.tunneling = buttons.pressed(GamepadButton(gamepad, SPRINT_BUTTON));
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+
+use crate::achievements::Achievements;
+use crate::localization::Localization;
+use crate::{
+    open_interior_cells, spawn_centered_text, ArenaConfig, Fonts, Footprint, GameRng, GameState,
+    Materials, PlayerId, Position, Score, Size, SpawnClaims, WorldSteps,
+};
+
+/// Gems scattered over the arena at the start of each game.
+const GEM_COUNT: usize = 5;
+
+pub(crate) struct Gem;
+
+pub(crate) struct LevelClearUi;
+
+fn spawn_gems(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    materials: Res<Materials>,
+    mut rng: ResMut<GameRng>,
+    mut claims: ResMut<SpawnClaims>,
+) {
+    let mut open = open_interior_cells(&arena, &claims);
+    for _ in 0..GEM_COUNT {
+        if open.is_empty() {
+            break;
+        }
+        let index = rng.index(open.len());
+        let position = open.swap_remove(index);
+        claims.claim(position);
+        spawn_gem(&mut commands, &materials, position);
+    }
+}
+
+pub(crate) fn spawn_gem(commands: &mut Commands, materials: &Materials, position: Position) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.gem_material.clone(),
+            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+            ..Default::default()
+        })
+        .insert(Gem)
+        .insert(position)
+        .insert(Size::square(0.3));
+}
+
+fn collect_gems(
+    mut commands: Commands,
+    mut score: ResMut<Score>,
+    mut achievements: ResMut<Achievements>,
+    fonts: Res<Fonts>,
+    materials: Res<Materials>,
+    localization: Res<Localization>,
+    players: Query<(&PlayerId, &Position, &Footprint)>,
+    gems: Query<(Entity, &Position), With<Gem>>,
+) {
+    let mut collected: Vec<Entity> = Vec::new();
+    for (id, player, footprint) in players.iter() {
+        for (gem, pos) in gems.iter() {
+            if footprint.covers(player, pos) && !collected.contains(&gem) {
+                commands.entity(gem).despawn();
+                score.record_gem(*id);
+                collected.push(gem);
+            }
+        }
+    }
+    if !collected.is_empty() && gems.iter().count() == collected.len() {
+        let message = spawn_centered_text(
+            &mut commands,
+            &fonts,
+            &materials,
+            localization.t("level.clear"),
+        );
+        commands.entity(message).insert(LevelClearUi);
+        achievements.levels_cleared += 1;
+    }
+}
+
+pub(crate) struct GemPlugin;
+
+impl Plugin for GemPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing)
+                .with_system(spawn_gems.system().label("gems").after("enemies")),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(
+                collect_gems
+                    .system()
+                    .label(WorldSteps::Gems)
+                    .after(WorldSteps::Traps),
+            ),
+        );
+    }
+}
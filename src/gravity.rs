@@ -0,0 +1,114 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::boulder::Boulder;
+use crate::grid::{entities_at, first_wall_at, WorldGrid};
+use crate::{
+    ArenaConfig, Direction, Footprint, GameState, Lives, MoveAnimation, Player, Position, Timesteps,
+};
+
+/// Side-on variant: players drop one cell per action tick while the cells
+/// under them are open, and a long fall costs lives. Enabled from
+/// `config.toml`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FallDamageConfig {
+    pub enabled: bool,
+    /// Cells a player can drop without getting hurt.
+    pub safe_cells: u32,
+    /// Lives lost for each cell dropped past `safe_cells`.
+    pub per_cell: u8,
+}
+
+impl Default for FallDamageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            safe_cells: 3,
+            per_cell: 1,
+        }
+    }
+}
+
+/// A player on the way down. Counting starts from the cell they stood on, so
+/// digging out the floor drops them one cell, well inside `safe_cells`.
+pub(crate) struct Falling {
+    cells: u32,
+    /// Where the last drop left them. Anywhere else, they walked, respawned or
+    /// went through a portal since, and the count starts over.
+    at: Position,
+}
+
+/// Walls, boulders, other players and the arena edge hold a player up; an
+/// enemy doesn't, and catches them as usual.
+pub(crate) fn player_gravity(
+    mut commands: Commands,
+    config: Res<FallDamageConfig>,
+    arena: Res<ArenaConfig>,
+    timesteps: Res<Timesteps>,
+    grid: Res<WorldGrid>,
+    mut state: ResMut<State<GameState>>,
+    mut lives: ResMut<Lives>,
+    boulders: Query<(), With<Boulder>>,
+    mut players: Query<(Entity, &mut Position, &Footprint, Option<&mut Falling>), With<Player>>,
+) {
+    if !config.enabled {
+        return;
+    }
+    let occupied: Vec<(Entity, Vec<Position>)> = players
+        .iter_mut()
+        .map(|(e, pos, footprint, _)| (e, footprint.cells(&pos)))
+        .collect();
+    for (e, mut pos, footprint, falling) in players.iter_mut() {
+        let cells = footprint.cells(&pos);
+        let open = cells
+            .iter()
+            .map(|cell| cell.translated(Direction::Down))
+            .filter(|below| !cells.contains(below))
+            .all(|below| {
+                arena.contains(&below)
+                    && first_wall_at(&grid, &below).is_none()
+                    && !entities_at(&grid, &below)
+                        .iter()
+                        .any(|b| boulders.get(*b).is_ok())
+                    && !occupied
+                        .iter()
+                        .any(|(other, cells)| *other != e && cells.contains(&below))
+            });
+        let fallen = falling
+            .as_ref()
+            .filter(|falling| falling.at == *pos)
+            .map_or(0, |falling| falling.cells);
+        if open {
+            let from = *pos;
+            *pos = pos.translated(Direction::Down);
+            commands
+                .entity(e)
+                .insert(MoveAnimation::new(
+                    from,
+                    *pos,
+                    timesteps.action_step() as f32,
+                ))
+                .insert(Falling {
+                    cells: fallen + 1,
+                    at: *pos,
+                });
+            continue;
+        }
+        if falling.is_none() {
+            continue;
+        }
+        commands.entity(e).remove::<Falling>();
+        let damage = fallen
+            .saturating_sub(config.safe_cells)
+            .saturating_mul(config.per_cell as u32);
+        if damage == 0 {
+            continue;
+        }
+        lives.0 = lives.0.saturating_sub(damage.min(u8::MAX as u32) as u8);
+        if lives.0 == 0 {
+            state.overwrite_set(GameState::GameOver).unwrap();
+            return;
+        }
+    }
+}
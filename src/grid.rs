@@ -0,0 +1,158 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use smallvec::SmallVec;
+
+use crate::{Footprint, Position, Wall};
+
+#[derive(Clone, Copy)]
+struct Occupant {
+    entity: Entity,
+    wall: bool,
+}
+
+/// Cell occupancy, kept in sync as entities spawn, move and despawn so
+/// adjacency checks are a single lookup instead of a scan over every wall.
+/// Anything with a `Footprint` is listed in every cell it covers.
+#[derive(Default)]
+pub(crate) struct WorldGrid {
+    cells: HashMap<Position, SmallVec<[Occupant; 2]>>,
+    located: HashMap<Entity, SmallVec<[Position; 1]>>,
+}
+
+impl WorldGrid {
+    fn remove(&mut self, entity: Entity) {
+        let cells = match self.located.remove(&entity) {
+            Some(cells) => cells,
+            None => return,
+        };
+        for pos in cells {
+            if let Some(occupants) = self.cells.get_mut(&pos) {
+                occupants.retain(|o| o.entity != entity);
+                if occupants.is_empty() {
+                    self.cells.remove(&pos);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, entity: Entity, cells: SmallVec<[Position; 1]>, wall: bool) {
+        self.remove(entity);
+        for pos in &cells {
+            self.cells
+                .entry(*pos)
+                .or_default()
+                .push(Occupant { entity, wall });
+        }
+        self.located.insert(entity, cells);
+    }
+}
+
+pub(crate) fn entities_at(grid: &WorldGrid, pos: &Position) -> SmallVec<[Entity; 2]> {
+    grid.cells
+        .get(pos)
+        .map(|occupants| occupants.iter().map(|o| o.entity).collect())
+        .unwrap_or_default()
+}
+
+pub(crate) fn first_wall_at(grid: &WorldGrid, pos: &Position) -> Option<Entity> {
+    grid.cells
+        .get(pos)?
+        .iter()
+        .find(|o| o.wall)
+        .map(|o| o.entity)
+}
+
+/// Cells holding more than one entity, which collision assumes never happens.
+pub(crate) fn crowded_cells(
+    grid: &WorldGrid,
+) -> impl Iterator<Item = (Position, SmallVec<[Entity; 2]>)> + '_ {
+    grid.cells
+        .iter()
+        .filter(|(_, occupants)| occupants.len() > 1)
+        .map(|(pos, occupants)| (*pos, occupants.iter().map(|o| o.entity).collect()))
+}
+
+/// Applies position changes and removals since this system last ran. Removals
+/// are only reported for the frame they happen in, so this also runs every
+/// frame, not just on action ticks; applying a change twice is harmless.
+pub(crate) fn update_world_grid(
+    mut grid: ResMut<WorldGrid>,
+    moved: Query<(Entity, &Position, Option<&Wall>, Option<&Footprint>), Changed<Position>>,
+    removed: RemovedComponents<Position>,
+) {
+    for entity in removed.iter() {
+        grid.remove(entity);
+    }
+    for (entity, pos, wall, footprint) in moved.iter() {
+        let cells = match footprint {
+            Some(footprint) => footprint.cells(pos).into_iter().collect(),
+            None => smallvec::smallvec![*pos],
+        };
+        grid.insert(entity, cells, wall.is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(world: &mut World) {
+        let mut stage = SystemStage::single(update_world_grid.system());
+        stage.run(world);
+        world.clear_trackers();
+    }
+
+    #[test]
+    fn tracks_several_entities_on_one_cell() {
+        let mut world = World::default();
+        world.insert_resource(WorldGrid::default());
+        let cell = Position { x: 2, y: 2 };
+        let wall = world.spawn().insert(cell).insert(Wall).id();
+        let gem = world.spawn().insert(cell).id();
+        let player = world.spawn().insert(cell).id();
+        run(&mut world);
+
+        let grid = world.get_resource::<WorldGrid>().unwrap();
+        let occupants = entities_at(grid, &cell);
+        assert_eq!(occupants.len(), 3);
+        assert!([wall, gem, player].iter().all(|e| occupants.contains(e)));
+        assert_eq!(first_wall_at(grid, &cell), Some(wall));
+        let crowded: Vec<_> = crowded_cells(grid).collect();
+        assert_eq!(crowded.len(), 1);
+        assert_eq!(crowded[0].0, cell);
+
+        // Moving one out leaves the others listed
+        let next = Position { x: 3, y: 2 };
+        *world.get_mut::<Position>(gem).unwrap() = next;
+        run(&mut world);
+        let grid = world.get_resource::<WorldGrid>().unwrap();
+        assert_eq!(entities_at(grid, &cell).len(), 2);
+        assert_eq!(entities_at(grid, &next).as_slice(), &[gem]);
+
+        // Despawning the wall frees the cell for building and digging checks
+        world.despawn(wall);
+        run(&mut world);
+        let grid = world.get_resource::<WorldGrid>().unwrap();
+        assert_eq!(first_wall_at(grid, &cell), None);
+        assert_eq!(entities_at(grid, &cell).as_slice(), &[player]);
+        assert_eq!(crowded_cells(grid).count(), 0);
+    }
+
+    #[test]
+    fn lists_a_footprint_in_every_cell_it_covers() {
+        let mut world = World::default();
+        world.insert_resource(WorldGrid::default());
+        let big = world
+            .spawn()
+            .insert(Position { x: 5, y: 5 })
+            .insert(Footprint::square(2))
+            .id();
+        run(&mut world);
+
+        let grid = world.get_resource::<WorldGrid>().unwrap();
+        for (x, y) in [(5, 5), (6, 5), (5, 6), (6, 6)] {
+            assert_eq!(entities_at(grid, &Position { x, y }).as_slice(), &[big]);
+        }
+        assert!(entities_at(grid, &Position { x: 7, y: 5 }).is_empty());
+    }
+}
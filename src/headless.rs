@@ -0,0 +1,283 @@
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::asset::AssetPlugin;
+use bevy::core::CorePlugin;
+use bevy::diagnostic::DiagnosticsPlugin;
+use bevy::input::InputPlugin;
+use bevy::prelude::*;
+use bevy::window::{WindowId, WindowPlugin};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::config::GameConfig;
+use crate::enemy::Enemy;
+use crate::level::Level;
+use crate::localization::{Localization, DEFAULT_LANGUAGE};
+use crate::{
+    add_game, Action, Boundary, Direction, GameState, Player, PlayerActions, PlayerId, Position,
+    Score, TimeScale, Wall,
+};
+
+/// Simulated time each headless tick stands for, whatever it really took.
+const SIM_FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Set in a headless run, which leaves the high score and achievements on disk
+/// alone.
+pub(crate) struct Headless;
+
+pub(crate) struct SimConfig {
+    pub(crate) game: GameConfig,
+    pub(crate) level: Option<Level>,
+    pub(crate) seed: u64,
+}
+
+/// Steers a player as if their keys were pressed on one tick.
+#[derive(Copy, Clone)]
+pub(crate) struct ScriptedAction {
+    pub(crate) tick: u32,
+    pub(crate) player: u8,
+    pub(crate) action: Action,
+    pub(crate) direction: Direction,
+}
+
+#[derive(PartialEq, Debug)]
+pub(crate) struct SimResult {
+    /// Fewer than asked for when the game ended early.
+    pub(crate) ticks: u32,
+    pub(crate) state: GameState,
+    pub(crate) dug: u32,
+    pub(crate) built: u32,
+    pub(crate) gems: u32,
+    pub(crate) points: u32,
+    /// By id.
+    pub(crate) players: Vec<Position>,
+    pub(crate) walls: usize,
+    pub(crate) enemies: usize,
+    /// How long the run really took.
+    pub(crate) elapsed: Duration,
+}
+
+impl fmt::Display for SimResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let simulated = SIM_FRAME * self.ticks;
+        write!(
+            f,
+            "{} ticks ({:.1}s simulated) in {:.3}s, ending {:?}: dug {}, built {}, gems {}, \
+             {} points, {} walls, {} enemies, players at {:?}",
+            self.ticks,
+            simulated.as_secs_f64(),
+            self.elapsed.as_secs_f64(),
+            self.state,
+            self.dug,
+            self.built,
+            self.gems,
+            self.points,
+            self.walls,
+            self.enemies,
+            self.players
+        )
+    }
+}
+
+/// Ticks run so far, counted at the end of each.
+#[derive(Default)]
+struct SimTick(u32);
+
+struct Script(Vec<ScriptedAction>);
+
+fn play_script(
+    tick: Res<SimTick>,
+    script: Res<Script>,
+    mut players: Query<(&PlayerId, &mut Player)>,
+) {
+    for step in script.0.iter().filter(|step| step.tick == tick.0) {
+        for (id, mut player) in players.iter_mut() {
+            if id.0 == step.player {
+                player.action = step.action;
+                player.face_direction = step.direction;
+            }
+        }
+    }
+}
+
+fn count_tick(mut tick: ResMut<SimTick>) {
+    tick.0 += 1;
+}
+
+/// Plays a generated game (or `config.level`) from its first tick with no
+/// window and no frame pacing, for up to `ticks` ticks of `SIM_FRAME` each,
+/// stopping early if the game ends. The same config and script always play
+/// out the same way: time comes from the tick count rather than the clock,
+/// and everything that moves the game on runs in a fixed order (see
+/// `PlayerActions` and `WorldSteps`), which the scheduler would otherwise
+/// settle differently from one app to the next.
+pub(crate) fn simulate_headless(
+    config: SimConfig,
+    script: &[ScriptedAction],
+    ticks: u32,
+) -> SimResult {
+    let mut builder = App::build();
+    builder.insert_resource(DefaultTaskPoolOptions::with_num_threads(1));
+    add_game(
+        &mut builder,
+        config.game,
+        config.level,
+        Localization::load(DEFAULT_LANGUAGE),
+        Some(config.seed),
+        GameState::Playing,
+    );
+    builder
+        .insert_resource(Headless)
+        .insert_resource(TimeScale {
+            rate: 1.0,
+            fixed_frame: Some(SIM_FRAME),
+        })
+        .insert_resource(Script(script.to_vec()))
+        .init_resource::<SimTick>()
+        .add_plugin(CorePlugin::default())
+        .add_plugin(ScheduleRunnerPlugin::default())
+        .add_plugin(DiagnosticsPlugin)
+        .add_plugin(InputPlugin)
+        .add_plugin(WindowPlugin {
+            add_primary_window: false,
+            exit_on_close: false,
+        })
+        .add_plugin(AssetPlugin)
+        .add_asset::<ColorMaterial>()
+        .add_asset::<Font>()
+        .add_asset::<Texture>()
+        .add_asset::<TextureAtlas>()
+        .add_asset::<AudioSource>()
+        .insert_resource(Audio::<AudioSource>::default())
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(
+                play_script
+                    .system()
+                    .after(PlayerActions::GamepadInput)
+                    .before(PlayerActions::Grid),
+            ),
+        )
+        .add_system_to_stage(CoreStage::Last, count_tick.system());
+    // Never opened, but the layout systems measure it
+    let window = Window::new(
+        WindowId::primary(),
+        &WindowDescriptor::default(),
+        400,
+        400,
+        1.0,
+        None,
+    );
+    builder
+        .world_mut()
+        .get_resource_mut::<Windows>()
+        .unwrap()
+        .add(window);
+
+    let mut app = builder.app;
+    let start = Instant::now();
+    for _ in 0..ticks {
+        app.update();
+        if *app
+            .world
+            .get_resource::<State<GameState>>()
+            .unwrap()
+            .current()
+            != GameState::Playing
+        {
+            break;
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let world = &mut app.world;
+    let mut players: Vec<(PlayerId, Position)> = world
+        .query_filtered::<(&PlayerId, &Position), With<Player>>()
+        .iter(world)
+        .map(|(id, position)| (*id, *position))
+        .collect();
+    players.sort_by_key(|(id, _)| *id);
+    let walls = world
+        .query_filtered::<(), (With<Wall>, Without<Boundary>)>()
+        .iter(world)
+        .count();
+    let enemies = world
+        .query_filtered::<(), With<Enemy>>()
+        .iter(world)
+        .count();
+    let score = world.get_resource::<Score>().unwrap();
+    SimResult {
+        ticks: world.get_resource::<SimTick>().unwrap().0,
+        state: world
+            .get_resource::<State<GameState>>()
+            .unwrap()
+            .current()
+            .clone(),
+        dug: score.dug,
+        built: score.built,
+        gems: score.gems,
+        points: score.points,
+        players: players.into_iter().map(|(_, position)| position).collect(),
+        walls,
+        enemies,
+        elapsed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(script: &[ScriptedAction], ticks: u32) -> SimResult {
+        let config = SimConfig {
+            game: GameConfig::default(),
+            level: None,
+            seed: 7,
+        };
+        simulate_headless(config, script, ticks)
+    }
+
+    #[test]
+    fn runs_replay_exactly() {
+        let script = [
+            ScriptedAction {
+                tick: 5,
+                player: 0,
+                action: Action::Move,
+                direction: Direction::Right,
+            },
+            ScriptedAction {
+                tick: 30,
+                player: 0,
+                action: Action::Dig,
+                direction: Direction::Up,
+            },
+        ];
+        let first = run(&script, 600);
+        let second = run(&script, 600);
+        assert!(first.ticks > 0);
+        assert!(first.walls > 0, "no walls spawned in {}", first);
+        assert_eq!(
+            SimResult {
+                elapsed: Duration::default(),
+                ..first
+            },
+            SimResult {
+                elapsed: Duration::default(),
+                ..second
+            }
+        );
+    }
+
+    #[test]
+    fn scripted_moves_are_played() {
+        let still = run(&[], 60);
+        let script = [ScriptedAction {
+            tick: 10,
+            player: 0,
+            action: Action::Move,
+            direction: Direction::Right,
+        }];
+        let moved = run(&script, 60);
+        assert_eq!(still.ticks, 60);
+        assert_ne!(still.players[0], moved.players[0]);
+    }
+}
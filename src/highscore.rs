@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::headless::Headless;
+use crate::Score;
+
+const HIGH_SCORE_PATH: &str = "highscore.json";
+
+/// Best `dug + built` total so far, kept in `highscore.json` next to the game.
+pub struct HighScore(pub u32);
+
+#[derive(Serialize, Deserialize)]
+struct SavedHighScore {
+    best: u32,
+}
+
+impl HighScore {
+    /// A missing or unreadable file just means there's no best yet.
+    pub fn load() -> Self {
+        let saved = fs::read_to_string(HIGH_SCORE_PATH)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<SavedHighScore>(&contents).ok());
+        Self(saved.map_or(0, |saved| saved.best))
+    }
+
+    fn save(&self) {
+        let saved = SavedHighScore { best: self.0 };
+        let result = serde_json::to_string(&saved)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(HIGH_SCORE_PATH, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!("Could not save high score to {}: {}", HIGH_SCORE_PATH, e);
+        }
+    }
+}
+
+pub(crate) fn record_high_score(
+    score: Res<Score>,
+    headless: Option<Res<Headless>>,
+    mut high_score: ResMut<HighScore>,
+) {
+    let total = score.points + score.built;
+    if total > high_score.0 {
+        high_score.0 = total;
+        if headless.is_none() {
+            high_score.save();
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+/// Distance kept between HUD elements and the window edges.
+const HUD_MARGIN: f32 = 10.0;
+/// Gap between elements stacked on the same anchor.
+const HUD_SPACING: f32 = 4.0;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
+pub enum HudAnchor {
+    TopLeft,
+    TopCenter,
+    TopRight,
+    BottomCenter,
+    BottomRight,
+}
+
+/// Placed on a UI node to let `layout_hud` position it. Elements sharing an anchor
+/// are stacked away from the window edge in increasing `order`.
+pub struct HudElement {
+    pub anchor: HudAnchor,
+    pub order: u32,
+}
+
+pub fn layout_hud(windows: Res<Windows>, mut elements: Query<(&HudElement, &Node, &mut Style)>) {
+    let window = windows.get_primary().unwrap();
+    let mut elements: Vec<_> = elements.iter_mut().collect();
+    elements.sort_by_key(|(element, _, _)| (element.anchor, element.order));
+    let mut stacks: HashMap<HudAnchor, f32> = HashMap::default();
+    for (element, node, mut style) in elements {
+        let offset = stacks.entry(element.anchor).or_insert(HUD_MARGIN);
+        let mut position = Rect {
+            left: Val::Undefined,
+            right: Val::Undefined,
+            top: Val::Undefined,
+            bottom: Val::Undefined,
+        };
+        match element.anchor {
+            HudAnchor::TopLeft | HudAnchor::TopCenter | HudAnchor::TopRight => {
+                position.top = Val::Px(*offset)
+            }
+            HudAnchor::BottomCenter | HudAnchor::BottomRight => position.bottom = Val::Px(*offset),
+        }
+        match element.anchor {
+            HudAnchor::TopLeft => position.left = Val::Px(HUD_MARGIN),
+            HudAnchor::TopRight | HudAnchor::BottomRight => position.right = Val::Px(HUD_MARGIN),
+            HudAnchor::TopCenter | HudAnchor::BottomCenter => {
+                position.left = Val::Px(((window.width() - node.size.x) / 2.).max(HUD_MARGIN))
+            }
+        }
+        *offset += node.size.y + HUD_SPACING;
+        // Only write when something moved so change detection stays quiet
+        if style.position_type != PositionType::Absolute || style.position != position {
+            style.position_type = PositionType::Absolute;
+            style.position = position;
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::{Direction, PlayerId};
+
+/// Optional override for the default layout, next to the game.
+const KEY_BINDINGS_PATH: &str = "keybindings.json";
+
+/// One player's keys. Each direction takes a list so arrows and letters can
+/// share a move.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlayerKeys {
+    pub up: Vec<KeyCode>,
+    pub down: Vec<KeyCode>,
+    pub left: Vec<KeyCode>,
+    pub right: Vec<KeyCode>,
+    pub diagonals: Vec<(KeyCode, Direction)>,
+    pub action: KeyCode,
+    pub sprint: KeyCode,
+    /// Held with a direction to face it without stepping.
+    #[serde(default)]
+    pub turn: Option<KeyCode>,
+    /// Throws a carried rock instead of building with it.
+    #[serde(default)]
+    pub throw: Option<KeyCode>,
+    /// Lays a carried rock as a trap instead of building with it.
+    #[serde(default)]
+    pub trap: Option<KeyCode>,
+}
+
+/// Keyboard layout for every local player, in player order.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyBindings {
+    pub players: Vec<PlayerKeys>,
+}
+
+impl Default for KeyBindings {
+    // Player 0 keeps the original HJKL/arrows layout with YUBN diagonals, player 1
+    // takes WASD and has no spare keys around it for diagonals
+    fn default() -> Self {
+        Self {
+            players: vec![
+                PlayerKeys {
+                    up: vec![KeyCode::K, KeyCode::Up],
+                    down: vec![KeyCode::J, KeyCode::Down],
+                    left: vec![KeyCode::H, KeyCode::Left],
+                    right: vec![KeyCode::L, KeyCode::Right],
+                    diagonals: vec![
+                        (KeyCode::Y, Direction::UpLeft),
+                        (KeyCode::U, Direction::UpRight),
+                        (KeyCode::B, Direction::DownLeft),
+                        (KeyCode::N, Direction::DownRight),
+                    ],
+                    action: KeyCode::Space,
+                    sprint: KeyCode::RShift,
+                    turn: Some(KeyCode::RControl),
+                    throw: Some(KeyCode::M),
+                    trap: Some(KeyCode::Comma),
+                },
+                PlayerKeys {
+                    up: vec![KeyCode::W],
+                    down: vec![KeyCode::S],
+                    left: vec![KeyCode::A],
+                    right: vec![KeyCode::D],
+                    diagonals: Vec::new(),
+                    action: KeyCode::E,
+                    sprint: KeyCode::LShift,
+                    turn: Some(KeyCode::LControl),
+                    throw: Some(KeyCode::Q),
+                    trap: Some(KeyCode::F),
+                },
+            ],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Reads `keybindings.json` if there is one, keeping the defaults when it is
+    /// missing, unreadable or lists no players.
+    pub fn load() -> Self {
+        let contents = match fs::read_to_string(KEY_BINDINGS_PATH) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+        match serde_json::from_str::<KeyBindings>(&contents) {
+            Ok(bindings) if !bindings.players.is_empty() => bindings,
+            Ok(_) => {
+                warn!(
+                    "{} lists no players, using the default keys",
+                    KEY_BINDINGS_PATH
+                );
+                Self::default()
+            }
+            Err(e) => {
+                warn!("Could not parse {}: {}", KEY_BINDINGS_PATH, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Players beyond the configured layouts wrap around to the first ones.
+    pub(crate) fn for_player(&self, id: &PlayerId) -> &PlayerKeys {
+        &self.players[id.0 as usize % self.players.len()]
+    }
+}
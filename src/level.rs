@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+use std::fs;
+
+use crate::{Boundary, Materials, Position, Size, Wall, MIN_ARENA_SIDE};
+
+pub const LEVEL_DIR: &str = "assets/levels";
+
+/// A designed layout: `#` is a wall, `.` is empty and `P` is a player start,
+/// numbered in reading order. `E` is the exit, or `e` for one that stays locked
+/// until every gem is collected. The first line is the top row. Walls on the
+/// outer edge become indestructible boundary. A `budget = N` line anywhere
+/// caps the walls that can be built.
+pub(crate) struct Level {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) walls: Vec<Position>,
+    starts: Vec<Position>,
+    pub(crate) budget: Option<u32>,
+    /// Where the exit is, and whether it needs the gems collected first.
+    pub(crate) exit: Option<(Position, bool)>,
+}
+
+impl Level {
+    pub(crate) fn start(&self, index: usize) -> Option<Position> {
+        self.starts.get(index).copied()
+    }
+
+    fn on_edge(&self, pos: &Position) -> bool {
+        pos.x == 0
+            || pos.y == 0
+            || pos.x == self.width as i32 - 1
+            || pos.y == self.height as i32 - 1
+    }
+}
+
+/// Reads a level, or `None` (with a warning) if it is missing, empty or too
+/// small to play in, so the caller can fall back to procedural walls.
+pub(crate) fn load_level(path: &str) -> Option<Level> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("Could not load level from {}: {}", path, e);
+            return None;
+        }
+    };
+    let level = parse_level(&contents);
+    match &level {
+        None => warn!("Level {} has no rows", path),
+        Some(level) if level.width < MIN_ARENA_SIDE || level.height < MIN_ARENA_SIDE => {
+            warn!(
+                "Level {} is {}x{}, too small to play in",
+                path, level.width, level.height
+            );
+            return None;
+        }
+        Some(_) => {}
+    }
+    level
+}
+
+/// `Some` for a `budget = N` line, holding the budget if `N` is a valid count.
+fn parse_budget(line: &str) -> Option<Option<u32>> {
+    let value = line
+        .strip_prefix("budget")?
+        .trim_start()
+        .strip_prefix('=')?
+        .trim();
+    match value.parse() {
+        Ok(budget) => Some(Some(budget)),
+        Err(e) => {
+            warn!("Ignoring level budget {:?}: {}", value, e);
+            Some(None)
+        }
+    }
+}
+
+fn parse_level(contents: &str) -> Option<Level> {
+    let mut budget = None;
+    let rows: Vec<&str> = contents
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .filter(|line| match parse_budget(line) {
+            Some(value) => {
+                budget = value;
+                false
+            }
+            None => true,
+        })
+        .collect();
+    let height = rows.len() as u32;
+    // Short rows are padded with empty cells
+    let width = rows.iter().map(|row| row.chars().count()).max()? as u32;
+    let mut level = Level {
+        width,
+        height,
+        walls: Vec::new(),
+        starts: Vec::new(),
+        budget,
+        exit: None,
+    };
+    for (row, line) in rows.iter().enumerate() {
+        let y = (height as usize - 1 - row) as i32;
+        for (x, cell) in line.chars().enumerate() {
+            let pos = Position { x: x as i32, y };
+            match cell {
+                '#' => level.walls.push(pos),
+                'P' => level.starts.push(pos),
+                'E' => level.exit = Some((pos, false)),
+                'e' => level.exit = Some((pos, true)),
+                '.' => {}
+                other => warn!(
+                    "Unknown level cell {:?} at {:?}, leaving it empty",
+                    other, pos
+                ),
+            }
+        }
+    }
+    Some(level)
+}
+
+pub(crate) fn spawn_level(
+    mut commands: Commands,
+    level: Option<Res<Level>>,
+    materials: Res<Materials>,
+) {
+    let level = match level {
+        Some(level) => level,
+        None => return,
+    };
+    for p in level.walls.iter() {
+        let boundary = level.on_edge(p);
+        let material = if boundary {
+            materials.boundary_material.clone()
+        } else {
+            materials.wall_material.clone()
+        };
+        let mut wall = commands.spawn_bundle(SpriteBundle {
+            material,
+            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+            ..Default::default()
+        });
+        wall.insert(Wall).insert(*p).insert(Size::square(0.8));
+        if boundary {
+            wall.insert(Boundary);
+        }
+    }
+}
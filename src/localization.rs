@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::fs;
+
+pub const DEFAULT_LANGUAGE: &str = "en";
+const LOCALE_DIR: &str = "assets/locale";
+// Compiled in so there is always something to fall back to
+const ENGLISH: &str = include_str!("../assets/locale/en.txt");
+
+/// Translation table for the selected language.
+/// Lookups fall back to English, and then to the key itself.
+pub struct Localization {
+    language: String,
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+impl Localization {
+    pub fn load(language: &str) -> Self {
+        let mut localization = Self {
+            language: String::new(),
+            strings: HashMap::default(),
+            fallback: parse_table(ENGLISH),
+        };
+        localization.set_language(language);
+        localization
+    }
+
+    pub fn set_language(&mut self, language: &str) {
+        let path = format!("{}/{}.txt", LOCALE_DIR, language);
+        self.strings = match fs::read_to_string(&path) {
+            Ok(contents) => parse_table(&contents),
+            Err(e) => {
+                warn!("Could not load translations from {}: {}", path, e);
+                HashMap::default()
+            }
+        };
+        self.language = language.to_string();
+    }
+
+    /// Moves to the next or previous language with a table in the locale
+    /// directory, wrapping at either end.
+    pub fn cycle_language(&mut self, forward: bool) {
+        let languages = available_languages();
+        let count = languages.len();
+        let index = languages
+            .iter()
+            .position(|l| *l == self.language)
+            .unwrap_or(0);
+        let next = if forward {
+            (index + 1) % count
+        } else {
+            (index + count - 1) % count
+        };
+        self.set_language(&languages[next]);
+    }
+
+    pub fn t<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings
+            .get(key)
+            .or_else(|| self.fallback.get(key))
+            .map(String::as_str)
+            .unwrap_or(key)
+    }
+}
+
+/// Every language with a table in the locale directory, in name order. English
+/// is always listed, since it's compiled in.
+fn available_languages() -> Vec<String> {
+    let mut languages: Vec<String> = fs::read_dir(LOCALE_DIR)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| {
+                    let path = entry.ok()?.path();
+                    if path.extension()? != "txt" {
+                        return None;
+                    }
+                    Some(path.file_stem()?.to_str()?.to_string())
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if !languages.iter().any(|l| l == DEFAULT_LANGUAGE) {
+        languages.push(DEFAULT_LANGUAGE.to_string());
+    }
+    languages.sort();
+    languages
+}
+
+fn parse_table(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Keep the window title in step with the selected language.
+pub fn localize_window_title(localization: Res<Localization>, mut windows: ResMut<Windows>) {
+    if !localization.is_changed() {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_title(localization.t("window.title").to_string());
+    }
+}
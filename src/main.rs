@@ -1,17 +1,165 @@
-use bevy::core::FixedTimestep;
+// Bevy system signatures trip these lints constantly
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
+
+use bevy::diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin};
+use bevy::ecs::component::Component;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
 use bevy::render::pass::ClearColor;
-use rand::prelude::random;
+use bevy::utils::{HashMap, HashSet};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::env;
+use std::time::Duration;
 
-const ARENA_WIDTH: u32 = 20;
-const ARENA_HEIGHT: u32 = 20;
+mod achievements;
+mod boulder;
+mod camera;
+mod compass;
+mod config;
+mod difficulty;
+mod editor;
+mod enemy;
+mod exit;
+mod fog;
+mod gamepad;
+mod gem;
+mod gravity;
+mod grid;
+mod headless;
+mod highscore;
+mod hud;
+mod keybindings;
+mod level;
+mod localization;
+mod menu;
+mod minimap;
+mod oxygen;
+mod palette;
+mod pathfinding;
+mod portal;
+mod projectile;
+mod save;
+mod share;
+mod trap;
+mod tween;
+mod water;
 
-#[derive(PartialEq, Copy, Clone, Debug)]
+use achievements::{Achievements, AchievementsPlugin};
+use boulder::{slide_system, Boulder, BoulderPlugin, Sliding};
+use camera::{
+    apply_camera_shake, camera_follow, cycle_camera_mode, free_camera, settle_camera_shake,
+    CameraFollow, CameraMode, CameraShake,
+};
+use compass::CompassPlugin;
+use config::{checked_rate, checked_seconds, GameConfig};
+use difficulty::{apply_difficulty, Difficulty, DifficultyBase};
+use editor::EditorPlugin;
+use enemy::{Enemy, EnemyHealth, EnemyPlugin};
+use exit::{Exit, ExitPlugin};
+use fog::{FogCell, FogPlugin};
+use gamepad::{action_held, gamepad_input};
+use gem::{Gem, GemPlugin, LevelClearUi};
+use gravity::player_gravity;
+use grid::{crowded_cells, entities_at, first_wall_at, update_world_grid, WorldGrid};
+use headless::{simulate_headless, SimConfig};
+use highscore::{record_high_score, HighScore};
+use hud::{layout_hud, HudAnchor, HudElement};
+use keybindings::KeyBindings;
+use level::{load_level, spawn_level, Level, LEVEL_DIR};
+use localization::{localize_window_title, Localization};
+use menu::MenuPlugin;
+use minimap::{render_minimap, spawn_minimap};
+use oxygen::{Air, Oxygen, OxygenBar, OxygenMode, OxygenPlugin};
+use palette::{Palette, PalettePlugin};
+use pathfinding::{adjacent_direction, find_path, reachable_cells};
+use portal::{portal_travel, Portal, PortalPlugin};
+use projectile::{move_projectiles, player_throw_action, Projectile};
+use save::SavePlugin;
+use share::{GameSeed, ShareCode, SharePlugin};
+use trap::{player_trap_action, Trap, TrapPlugin};
+use tween::{ColorTween, Easing, ScaleTween, TranslationTween, Tween, TweenDespawn, TweenPlugin};
+use water::{Drowned, Water, WaterPlugin};
+
+// The 2D camera sits just inside its far plane, so anything below -0.1 is clipped.
+const BACKGROUND_Z: f32 = -0.05;
+const BACKGROUND_TILE_SIZE: f32 = 40.0;
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
 struct Position {
     x: i32,
     y: i32,
 }
 
+/// Dimensions of the playfield in cells, including the boundary ring.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ArenaConfig {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        Self {
+            width: 20,
+            height: 20,
+        }
+    }
+}
+
+/// Smallest arena side that still has an interior inside the boundary ring.
+const MIN_ARENA_SIDE: u32 = 3;
+
+impl ArenaConfig {
+    /// Grows a side too small to hold an interior up to the minimum, with a
+    /// warning, since tile sizes and wall density divide by the interior.
+    pub(crate) fn validated(self) -> Self {
+        if self.width >= MIN_ARENA_SIDE && self.height >= MIN_ARENA_SIDE {
+            return self;
+        }
+        warn!(
+            "Arena {}x{} is smaller than {}x{}, growing it",
+            self.width, self.height, MIN_ARENA_SIDE, MIN_ARENA_SIDE
+        );
+        Self {
+            width: self.width.max(MIN_ARENA_SIDE),
+            height: self.height.max(MIN_ARENA_SIDE),
+        }
+    }
+
+    fn contains(&self, pos: &Position) -> bool {
+        pos.x >= 0 && pos.y >= 0 && pos.x < self.width as i32 && pos.y < self.height as i32
+    }
+
+    /// The cell a player steps to from `pos`. With wrapping on, stepping off
+    /// one edge comes back in at the opposite one.
+    fn step(&self, pos: &Position, dir: Direction, wrap: &WrapMode) -> Position {
+        let next = pos.translated(dir);
+        if !wrap.0 {
+            return next;
+        }
+        Position {
+            x: next.x.rem_euclid(self.width as i32),
+            y: next.y.rem_euclid(self.height as i32),
+        }
+    }
+}
+
+impl Position {
+    pub fn translated(&self, dir: Direction) -> Position {
+        let (dx, dy) = dir.offset();
+        Position {
+            x: self.x + dx,
+            y: self.y + dy,
+        }
+    }
+}
+
 struct Size {
     width: f32,
     height: f32,
@@ -26,6 +174,19 @@ impl Size {
     }
 }
 
+#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+pub enum GameState {
+    Menu,
+    Playing,
+    GameOver,
+    /// Every interior wall has been dug out.
+    Win,
+    /// Hand-placing walls to save as a level.
+    Editor,
+    /// Picking a save to play on from.
+    LoadGame,
+}
+
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum Action {
     Idle,
@@ -34,460 +195,3529 @@ pub enum Action {
     Build,
 }
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum Direction {
     Up,
     Down,
     Right,
     Left,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+impl Direction {
+    pub fn offset(&self) -> (i32, i32) {
+        match self {
+            Direction::Down => (0, -1),
+            Direction::Up => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+            Direction::UpLeft => (-1, 1),
+            Direction::UpRight => (1, 1),
+            Direction::DownLeft => (-1, -1),
+            Direction::DownRight => (1, -1),
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            Direction::UpLeft => Direction::DownRight,
+            Direction::UpRight => Direction::DownLeft,
+            Direction::DownLeft => Direction::UpRight,
+            Direction::DownRight => Direction::UpLeft,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
+pub struct PlayerId(pub u8);
+
+/// Number of local players sharing the keyboard.
+#[derive(Deserialize)]
+pub struct PlayerCount(pub u8);
+
+impl Default for PlayerCount {
+    fn default() -> Self {
+        Self(2)
+    }
+}
+
+/// Where player 0 starts, from `config.toml` or a level's first `P`.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct PlayerSpawn(Position);
+
+impl Default for PlayerSpawn {
+    fn default() -> Self {
+        Self(Position { x: 1, y: 1 })
+    }
+}
+
+impl PlayerSpawn {
+    /// Falls back to the bottom-left corner, with a warning, for a spawn outside
+    /// the boundary ring or on one of the level's walls.
+    fn validated(self, arena: &ArenaConfig, level: Option<&Level>) -> Self {
+        let Position { x, y } = self.0;
+        let inside = x > 0 && y > 0 && x < arena.width as i32 - 1 && y < arena.height as i32 - 1;
+        let on_wall = level.map_or(false, |l| l.walls.contains(&self.0));
+        if inside && !on_wall {
+            return self;
+        }
+        warn!(
+            "Player spawn {:?} is outside the arena or on a wall, using (1, 1)",
+            self.0
+        );
+        Self::default()
+    }
+}
+
+impl PlayerId {
+    /// Player 0 starts at the spawn; a level's own starts come next, and
+    /// otherwise player 1 starts in the corner opposite (1, 1).
+    fn start(&self, arena: &ArenaConfig, spawn: &PlayerSpawn, level: Option<&Level>) -> Position {
+        if self.0 == 0 {
+            return spawn.0;
+        }
+        if let Some(start) = level.and_then(|l| l.start(self.0 as usize)) {
+            return start;
+        }
+        Position {
+            x: arena.width as i32 - 2,
+            y: arena.height as i32 - 2,
+        }
+    }
 }
 
 struct Player {
     face_direction: Direction,
     action: Action,
-    has_rock: bool,
+    rocks: u32,
+    chaining: bool,
+    assisted: bool,
+    sprinting: bool,
+    stride: u32,
+    /// Dig every wall in a row instead of just the first; set by holding the
+    /// sprint key with the action key.
+    tunneling: bool,
+    /// Throw a rock at the next action tick.
+    throwing: bool,
+    /// Lay a trap at the next action tick.
+    trapping: bool,
+    /// Boulder this tick's move shoves ahead; set during validation.
+    pushing: Option<Entity>,
+}
+
+/// Side of the square of cells each player covers.
+#[derive(Deserialize)]
+pub struct PlayerSize(pub u32);
+
+impl Default for PlayerSize {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+/// Cells a player covers, as offsets from its `Position`, which is the
+/// bottom-left one. Players are a single cell unless `PlayerSize` says otherwise.
+struct Footprint(Vec<Position>);
+
+impl Footprint {
+    fn square(side: u32) -> Self {
+        let side = side.max(1) as i32;
+        Self(
+            (0..side)
+                .flat_map(|x| (0..side).map(move |y| Position { x, y }))
+                .collect(),
+        )
+    }
+
+    fn cells(&self, pos: &Position) -> Vec<Position> {
+        self.0
+            .iter()
+            .map(|offset| Position {
+                x: pos.x + offset.x,
+                y: pos.y + offset.y,
+            })
+            .collect()
+    }
+
+    /// Whether something at `pos` with this footprint covers `cell`.
+    fn covers(&self, pos: &Position, cell: &Position) -> bool {
+        self.0
+            .iter()
+            .any(|offset| pos.x + offset.x == cell.x && pos.y + offset.y == cell.y)
+    }
+
+    /// Cells just past the leading edge when facing `direction`. The first is
+    /// in line with `pos`, so a single cell gets back its plain step.
+    fn ahead(
+        &self,
+        pos: &Position,
+        direction: Direction,
+        arena: &ArenaConfig,
+        wrap: &WrapMode,
+    ) -> Vec<Position> {
+        let cells = self.cells(pos);
+        cells
+            .iter()
+            .map(|cell| arena.step(cell, direction, wrap))
+            .filter(|next| !cells.contains(next))
+            .collect()
+    }
+
+    /// Offset from `Position` to the middle of the footprint, in cells.
+    fn centre(&self) -> Vec2 {
+        let side = (self.0.len() as f32).sqrt();
+        Vec2::splat((side - 1.) / 2.)
+    }
 }
 struct Wall;
 struct Boundary;
 
-struct Materials {
-    player_material: Handle<ColorMaterial>,
-    wall_material: Handle<ColorMaterial>,
-    boundary_material: Handle<ColorMaterial>,
+/// What a wall is made of. Walls without one dig like dirt.
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+enum WallType {
+    Dirt,
+    Rock,
+    Bedrock,
 }
 
-fn setup(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
-    commands.insert_resource(Materials {
-        player_material: materials.add(Color::rgb(0.7, 0.7, 0.7).into()),
-        wall_material: materials.add(Color::rgb(0.5, 0.5, 0.).into()),
-        boundary_material: materials.add(Color::rgb(1., 0., 0.).into()),
-    });
+impl WallType {
+    /// Random wall mix: mostly dirt, some rock and the odd bedrock.
+    fn random(rng: &mut GameRng) -> Self {
+        match rng.chance() {
+            r if r < 0.7 => WallType::Dirt,
+            r if r < 0.95 => WallType::Rock,
+            _ => WallType::Bedrock,
+        }
+    }
+
+    fn hits(self) -> u32 {
+        match self {
+            WallType::Dirt => 1,
+            WallType::Rock => 2,
+            WallType::Bedrock => 0,
+        }
+    }
 }
 
-fn spawn_player(mut commands: Commands, materials: Res<Materials>) {
-    commands
-        .spawn_bundle(SpriteBundle {
-            material: materials.player_material.clone(),
-            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
-            ..Default::default()
-        })
-        .insert(Player {
-            action: Action::Idle,
-            face_direction: Direction::Up,
-            has_rock: false,
-        })
-        .insert(Position { x: 1, y: 1 })
-        .insert(Size::square(0.5));
+/// Digs a wall takes before it breaks.
+struct Toughness {
+    hits_remaining: u32,
 }
 
-fn spawn_boundaries(mut commands: Commands, materials: Res<Materials>) {
-    let mut boundary_positions: Vec<Position> = Vec::new();
-    for x in 0..ARENA_WIDTH {
-        boundary_positions.push(Position { x: x as i32, y: 0 });
-        boundary_positions.push(Position {
-            x: x as i32,
-            y: ARENA_HEIGHT as i32 - 1,
-        })
-    }
-    for y in 1..ARENA_HEIGHT - 1 {
-        boundary_positions.push(Position { x: 0, y: y as i32 });
-        boundary_positions.push(Position {
-            x: ARENA_WIDTH as i32 - 1,
-            y: y as i32,
-        });
+/// Bonus points hidden in a wall, paid out when it's dug away.
+pub(crate) struct Treasure(pub(crate) u32);
+
+const TREASURE_COLOR: Color = Color::rgb(1., 0.8, 0.1);
+
+/// Hides `bonus` in a wall, drawing a glint on it when treasure has a tell.
+fn insert_treasure(
+    wall: &mut EntityCommands,
+    materials: &Materials,
+    treasure: &TreasureWalls,
+    bonus: u32,
+) {
+    wall.insert(Treasure(bonus));
+    if !treasure.tell {
+        return;
     }
-    while let Some(p) = boundary_positions.pop() {
-        commands
+    wall.with_children(|parent| {
+        parent
             .spawn_bundle(SpriteBundle {
-                material: materials.boundary_material.clone(),
+                material: materials.treasure_material.clone(),
                 sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                transform: Transform::from_xyz(0., 0., 0.1),
                 ..Default::default()
             })
-            .insert(Boundary)
-            .insert(Wall)
-            .insert(p)
-            .insert(Size::square(0.8));
-    }
+            .insert(Size::square(0.2));
+    });
 }
 
-fn spawn_walls(
-    mut commands: Commands,
-    materials: Res<Materials>,
-    walls: Query<&Position, With<Wall>>,
-    players: Query<&Position, With<Player>>,
-) {
-    let mut target_position = Position { x: 0, y: 0 };
-    // Do not spawn on top of an existing wall or player
-    'outer: loop {
-        target_position.x = 1 + (random::<f32>() * (ARENA_WIDTH - 2) as f32) as i32;
-        target_position.y = 1 + (random::<f32>() * (ARENA_HEIGHT - 2) as f32) as i32;
-        for p in players.iter() {
-            if p == &target_position {
-                continue 'outer;
-            }
-        }
-        for p in walls.iter() {
-            if p == &target_position {
-                continue 'outer;
-            }
+/// Interior walls other than bedrock can be dug.
+fn is_diggable(
+    diggable: &Query<Option<&WallType>, (With<Wall>, Without<Boundary>)>,
+    e: Entity,
+) -> bool {
+    matches!(diggable.get(e), Ok(kind) if kind != Some(&WallType::Bedrock))
+}
+struct MainCamera;
+struct ParallaxBackground;
+struct ParallaxTile;
+
+/// Controls the background layer that scrolls slower than the camera.
+/// A `factor` of 0.0 pins the background to the screen, 1.0 scrolls it with the playfield.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ParallaxConfig {
+    pub enabled: bool,
+    pub factor: f32,
+}
+
+impl Default for ParallaxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            factor: 0.5,
         }
-        break;
     }
-    commands
-        .spawn_bundle(SpriteBundle {
-            material: materials.wall_material.clone(),
-            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
-            ..Default::default()
-        })
-        .insert(Wall)
-        .insert(target_position)
-        .insert(Size::square(0.8));
 }
 
-fn size_scaling(windows: Res<Windows>, mut q: Query<(&Size, &mut Sprite)>) {
-    let window = windows.get_primary().unwrap();
-    for (sprite_size, mut sprite) in q.iter_mut() {
-        sprite.size = Vec2::new(
-            sprite_size.width / ARENA_WIDTH as f32 * window.width() as f32,
-            sprite_size.height / ARENA_WIDTH as f32 * window.height() as f32,
-        );
-    }
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
+struct PlayerScore {
+    dug: u32,
+    built: u32,
+    gems: u32,
+    points: u32,
+}
+
+/// Team totals, plus the same tally for each player.
+#[derive(Default)]
+struct Score {
+    /// Walls dug, one each however they scored.
+    dug: u32,
+    built: u32,
+    gems: u32,
+    /// What the digging earned: each dig's combo plus any treasure found.
+    points: u32,
+    players: HashMap<PlayerId, PlayerScore>,
 }
 
-fn position_translation(windows: Res<Windows>, mut q: Query<(&Position, &mut Transform)>) {
-    fn convert(pos: f32, bound_window: f32, bound_game: f32) -> f32 {
-        let tile_size = bound_window / bound_game;
-        pos / bound_game * bound_window - (bound_window / 2.) + (tile_size / 2.)
+impl Score {
+    /// `points` is the current combo, so a chain of quick digs scores more.
+    fn record_dig(&mut self, id: PlayerId, points: u32) {
+        let player = self.players.entry(id).or_default();
+        player.dug += 1;
+        player.points += points;
+        self.dug += 1;
+        self.points += points;
     }
-    let window = windows.get_primary().unwrap();
-    for (pos, mut transform) in q.iter_mut() {
-        transform.translation = Vec3::new(
-            convert(pos.x as f32, window.width() as f32, ARENA_WIDTH as f32),
-            convert(pos.y as f32, window.height() as f32, ARENA_HEIGHT as f32),
-            0.0,
-        )
+
+    fn record_bonus(&mut self, id: PlayerId, points: u32) {
+        self.players.entry(id).or_default().points += points;
+        self.points += points;
+    }
+
+    fn record_build(&mut self, id: PlayerId) {
+        self.built += 1;
+        self.players.entry(id).or_default().built += 1;
+    }
+
+    fn record_gem(&mut self, id: PlayerId) {
+        self.gems += 1;
+        self.players.entry(id).or_default().gems += 1;
     }
 }
 
-pub struct PlayerActionPlugin;
+/// Lives shared by every local player.
+struct Lives(u8);
 
-#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
-pub enum PlayerActions {
-    Input,
-    InputValidation,
-    MoveAction,
-    DigAction,
-    BuildAction,
+impl Default for Lives {
+    fn default() -> Self {
+        Self(3)
+    }
 }
 
-impl Plugin for PlayerActionPlugin {
-    fn build(&self, app: &mut AppBuilder) {
-        app.add_system(
-            player_input
-                .system()
-                .label(PlayerActions::Input)
-                .before(PlayerActions::InputValidation),
-        )
-        .add_system_set(
-            SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(0.05))
-                .with_system(
-                    validate_player_action
-                        .system()
-                        .label(PlayerActions::InputValidation)
-                        .before(PlayerActions::MoveAction),
-                )
-                .with_system(
-                    player_move_action
-                        .system()
-                        .label(PlayerActions::MoveAction)
-                        .before(PlayerActions::DigAction),
-                )
-                .with_system(
-                    player_dig_action
-                        .system()
-                        .label(PlayerActions::DigAction)
-                        .before(PlayerActions::BuildAction),
-                )
-                .with_system(
-                    player_build_action
-                        .system()
-                        .label(PlayerActions::BuildAction),
-                ),
-        );
+/// Side of one frame in `sprites/player.png`, in pixels.
+const PLAYER_FRAME_SIZE: f32 = 16.0;
+/// Frames in the walk cycle, one per column of the sheet. The first doubles
+/// as the idle frame.
+const PLAYER_FRAMES: u32 = 4;
+const PLAYER_FRAME_SECONDS: f32 = 0.1;
+
+/// Steps through the walk cycle while a player moves.
+struct PlayerAnimation {
+    timer: Timer,
+    frame: u32,
+}
+
+struct ScoreText;
+
+struct LivesText;
+
+struct TimerText;
+
+struct RocksText;
+
+struct ComboText;
+
+struct BudgetText;
+
+struct DiagnosticsText;
+struct PauseUi;
+struct GameOverUi;
+struct WinUi;
+
+#[derive(Default)]
+pub struct Paused(pub bool);
+
+/// Colour of the first player's sprite under the default palette.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PlayerAppearance {
+    #[serde(deserialize_with = "config::rgb")]
+    pub color: Color,
+}
+
+impl Default for PlayerAppearance {
+    fn default() -> Self {
+        Self {
+            color: Color::rgb(0.7, 0.7, 0.7),
+        }
     }
 }
 
-#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
-pub enum PlayerMovement {
-    Input,
-    Movement,
+/// Colour of plain dirt walls under the default palette.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WallAppearance {
+    #[serde(deserialize_with = "config::rgb")]
+    pub color: Color,
 }
 
-fn player_input(keyboard_input: Res<Input<KeyCode>>, mut player_positions: Query<&mut Player>) {
-    for mut p in player_positions.iter_mut() {
-        if keyboard_input.just_pressed(KeyCode::J) || keyboard_input.just_pressed(KeyCode::Down) {
-            p.face_direction = Direction::Down;
-            p.action = Action::Move;
+impl Default for WallAppearance {
+    fn default() -> Self {
+        Self {
+            color: Color::rgb(0.5, 0.5, 0.),
         }
-        if keyboard_input.just_pressed(KeyCode::K) || keyboard_input.just_pressed(KeyCode::Up) {
-            p.face_direction = Direction::Up;
-            p.action = Action::Move;
+    }
+}
+
+struct Fonts {
+    ui: Handle<Font>,
+}
+
+/// Sheet the players are drawn from, with a row of walk frames for each of
+/// up, down, left and right. It's white, so tinting picks the player colour.
+#[cfg_attr(test, derive(Default))]
+struct PlayerSprites {
+    atlas: Handle<TextureAtlas>,
+}
+
+#[cfg_attr(test, derive(Default))]
+struct GameAudio {
+    dig: Handle<AudioSource>,
+    build: Handle<AudioSource>,
+}
+
+#[cfg_attr(test, derive(Default))]
+struct Materials {
+    player_material: Handle<ColorMaterial>,
+    second_player_material: Handle<ColorMaterial>,
+    wall_material: Handle<ColorMaterial>,
+    rock_material: Handle<ColorMaterial>,
+    bedrock_material: Handle<ColorMaterial>,
+    boundary_material: Handle<ColorMaterial>,
+    enemy_material: Handle<ColorMaterial>,
+    wanderer_material: Handle<ColorMaterial>,
+    digger_material: Handle<ColorMaterial>,
+    gem_material: Handle<ColorMaterial>,
+    background_material: Handle<ColorMaterial>,
+    overlay_material: Handle<ColorMaterial>,
+    minimap_material: Handle<ColorMaterial>,
+    boulder_material: Handle<ColorMaterial>,
+    highlight_material: Handle<ColorMaterial>,
+    crack_material: Handle<ColorMaterial>,
+    fog_material: Handle<ColorMaterial>,
+    explored_material: Handle<ColorMaterial>,
+    air_pocket_material: Handle<ColorMaterial>,
+    air_bubble_material: Handle<ColorMaterial>,
+    preview_material: Handle<ColorMaterial>,
+    blocked_preview_material: Handle<ColorMaterial>,
+    portal_material: Handle<ColorMaterial>,
+    exit_material: Handle<ColorMaterial>,
+    locked_exit_material: Handle<ColorMaterial>,
+    water_material: Handle<ColorMaterial>,
+    trap_material: Handle<ColorMaterial>,
+    treasure_material: Handle<ColorMaterial>,
+}
+
+impl Materials {
+    fn wall_for(&self, kind: WallType) -> Handle<ColorMaterial> {
+        match kind {
+            WallType::Dirt => self.wall_material.clone(),
+            WallType::Rock => self.rock_material.clone(),
+            WallType::Bedrock => self.bedrock_material.clone(),
         }
-        if keyboard_input.just_pressed(KeyCode::L) || keyboard_input.just_pressed(KeyCode::Right) {
-            p.face_direction = Direction::Right;
-            p.action = Action::Move;
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut atlases: ResMut<Assets<TextureAtlas>>,
+    player_appearance: Res<PlayerAppearance>,
+    wall_appearance: Res<WallAppearance>,
+    palette: Res<Palette>,
+) {
+    let colors = palette.colors(&player_appearance, &wall_appearance);
+    commands
+        .spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(MainCamera);
+    commands.spawn_bundle(UiCameraBundle::default());
+    commands.insert_resource(Materials {
+        player_material: materials.add(colors.player.into()),
+        second_player_material: materials.add(colors.second_player.into()),
+        wall_material: materials.add(colors.wall.into()),
+        rock_material: materials.add(Color::rgb(0.45, 0.4, 0.35).into()),
+        bedrock_material: materials.add(Color::rgb(0.2, 0.2, 0.25).into()),
+        boundary_material: materials.add(colors.boundary.into()),
+        enemy_material: materials.add(Color::rgb(0.8, 0.2, 0.6).into()),
+        wanderer_material: materials.add(Color::rgb(0.9, 0.6, 0.1).into()),
+        digger_material: materials.add(Color::rgb(0.5, 0.3, 0.9).into()),
+        gem_material: materials.add(Color::rgb(0.2, 0.9, 0.8).into()),
+        background_material: materials.add(Color::rgb(0.08, 0.08, 0.1).into()),
+        overlay_material: materials.add(Color::NONE.into()),
+        minimap_material: materials.add(Color::rgba(0., 0., 0., 0.5).into()),
+        boulder_material: materials.add(Color::rgb(0.55, 0.5, 0.45).into()),
+        highlight_material: materials.add(Color::rgba(1., 1., 1., 0.25).into()),
+        crack_material: materials.add(Color::rgba(0., 0., 0., 0.45).into()),
+        fog_material: materials.add(Color::rgb(0.02, 0.02, 0.03).into()),
+        explored_material: materials.add(Color::rgba(0.02, 0.02, 0.03, 0.6).into()),
+        air_pocket_material: materials.add(Color::rgba(0.6, 0.8, 1., 0.35).into()),
+        air_bubble_material: materials.add(Color::rgb(0.6, 0.85, 1.).into()),
+        preview_material: materials.add({
+            let mut color = colors.wall;
+            color.set_a(0.35);
+            color.into()
+        }),
+        blocked_preview_material: materials.add(Color::rgba(1., 0.2, 0.2, 0.35).into()),
+        portal_material: materials.add(Color::rgb(0.3, 1., 0.3).into()),
+        exit_material: materials.add(Color::rgb(1., 0.85, 0.2).into()),
+        locked_exit_material: materials.add(Color::rgb(0.4, 0.35, 0.2).into()),
+        water_material: materials.add(Color::rgba(0.15, 0.4, 0.9, 0.6).into()),
+        trap_material: materials.add(Color::rgba(0.7, 0.15, 0.1, 0.6).into()),
+        treasure_material: materials.add(TREASURE_COLOR.into()),
+    });
+    commands.insert_resource(Fonts {
+        ui: asset_server.load("fonts/DejaVuSansMono.ttf"),
+    });
+    let sheet = asset_server.load("sprites/player.png");
+    commands.insert_resource(PlayerSprites {
+        atlas: atlases.add(TextureAtlas::from_grid(
+            sheet,
+            Vec2::splat(PLAYER_FRAME_SIZE),
+            PLAYER_FRAMES as usize,
+            4,
+        )),
+    });
+    commands.insert_resource(HighScore::load());
+    commands.insert_resource(GameAudio {
+        dig: asset_server.load("sounds/dig.wav"),
+        build: asset_server.load("sounds/build.wav"),
+    });
+}
+
+/// A blank line of HUD text, filled in by whichever system reads `marker`.
+fn spawn_hud_text(
+    commands: &mut Commands,
+    fonts: &Fonts,
+    marker: impl Component,
+    anchor: HudAnchor,
+    order: u32,
+) {
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: fonts.ui.clone(),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(marker)
+        .insert(HudElement { anchor, order });
+}
+
+fn spawn_score_text(mut commands: Commands, fonts: Res<Fonts>) {
+    spawn_hud_text(&mut commands, &fonts, ScoreText, HudAnchor::TopRight, 0);
+}
+
+fn spawn_lives_text(mut commands: Commands, fonts: Res<Fonts>) {
+    spawn_hud_text(&mut commands, &fonts, LivesText, HudAnchor::TopLeft, 0);
+}
+
+fn spawn_timer_text(mut commands: Commands, fonts: Res<Fonts>) {
+    spawn_hud_text(&mut commands, &fonts, TimerText, HudAnchor::TopCenter, 0);
+}
+
+fn update_timer_text(
+    timer: Option<Res<GameTimer>>,
+    localization: Res<Localization>,
+    mut texts: Query<&mut Text, With<TimerText>>,
+) {
+    let value = match timer {
+        Some(timer) => {
+            let remaining = timer.0.duration().as_secs_f32() - timer.0.elapsed_secs();
+            format!("{}: {}", localization.t("hud.time"), remaining.ceil())
         }
-        if keyboard_input.just_pressed(KeyCode::H) || keyboard_input.just_pressed(KeyCode::Left) {
-            p.face_direction = Direction::Left;
-            p.action = Action::Move;
+        None => String::new(),
+    };
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
         }
-        if keyboard_input.just_pressed(KeyCode::Space) {
-            if !p.has_rock {
-                p.action = Action::Dig;
-            }
-            if p.has_rock {
-                p.action = Action::Build;
+    }
+}
+
+fn spawn_rocks_text(mut commands: Commands, fonts: Res<Fonts>) {
+    spawn_hud_text(&mut commands, &fonts, RocksText, HudAnchor::TopLeft, 1);
+}
+
+fn update_rocks_text(
+    capacity: Res<RockCapacity>,
+    localization: Res<Localization>,
+    players: Query<(&PlayerId, &Player)>,
+    mut texts: Query<&mut Text, With<RocksText>>,
+) {
+    let mut carried: Vec<(PlayerId, u32)> = players.iter().map(|(id, p)| (*id, p.rocks)).collect();
+    carried.sort_by_key(|(id, _)| *id);
+    let value = carried
+        .iter()
+        .map(|(id, rocks)| {
+            let count = format!("{}: {}/{}", localization.t("hud.rocks"), rocks, capacity.0);
+            if carried.len() > 1 {
+                format!("{}{} {}", localization.t("hud.player"), id.0 + 1, count)
+            } else {
+                count
             }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
         }
     }
 }
 
-fn validate_player_action(
-    mut players: Query<(&Position, &mut Player)>,
-    walls: Query<&Position, With<Wall>>,
+fn spawn_combo_text(mut commands: Commands, fonts: Res<Fonts>) {
+    spawn_hud_text(&mut commands, &fonts, ComboText, HudAnchor::TopRight, 1);
+}
+
+fn spawn_budget_text(mut commands: Commands, fonts: Res<Fonts>) {
+    spawn_hud_text(&mut commands, &fonts, BudgetText, HudAnchor::TopRight, 2);
+}
+
+/// Blank while building is unlimited.
+fn update_budget_text(
+    budget: Option<Res<BuildBudget>>,
+    localization: Res<Localization>,
+    mut texts: Query<&mut Text, With<BudgetText>>,
+) {
+    let value = budget.map_or(String::new(), |budget| {
+        format!("{}: {}", localization.t("hud.budget"), budget.0)
+    });
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+/// Only shown once a combo is actually running.
+fn update_combo_text(
+    combo: Res<Combo>,
+    localization: Res<Localization>,
+    mut texts: Query<&mut Text, With<ComboText>>,
+) {
+    if !combo.is_changed() && !localization.is_changed() {
+        return;
+    }
+    let value = if combo.count > 1 {
+        format!("{} x{}", localization.t("hud.combo"), combo.count)
+    } else {
+        String::new()
+    };
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+fn spawn_diagnostics_text(mut commands: Commands, fonts: Res<Fonts>) {
+    spawn_hud_text(
+        &mut commands,
+        &fonts,
+        DiagnosticsText,
+        HudAnchor::TopLeft,
+        2,
+    );
+}
+
+fn toggle_debug_overlay(keyboard_input: Res<Input<KeyCode>>, mut overlay: ResMut<DebugOverlay>) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        overlay.0 = !overlay.0;
+    }
+}
+
+fn diagnostics_text(
+    overlay: Res<DebugOverlay>,
+    diagnostics: Res<Diagnostics>,
+    localization: Res<Localization>,
+    walls: Query<(), With<Wall>>,
+    mut texts: Query<&mut Text, With<DiagnosticsText>>,
+) {
+    let value = if overlay.0 {
+        let fps = diagnostics
+            .get(FrameTimeDiagnosticsPlugin::FPS)
+            .and_then(|fps| fps.average())
+            .unwrap_or_default();
+        format!(
+            "{}: {:.0}  {}: {}",
+            localization.t("debug.fps"),
+            fps,
+            localization.t("debug.walls"),
+            walls.iter().count()
+        )
+    } else {
+        String::new()
+    };
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+fn update_lives_text(
+    lives: Res<Lives>,
+    localization: Res<Localization>,
+    mut texts: Query<&mut Text, With<LivesText>>,
+) {
+    if !lives.is_changed() && !localization.is_changed() {
+        return;
+    }
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = format!("{}: {}", localization.t("hud.lives"), lives.0);
+    }
+}
+
+fn simulating(state: &State<GameState>, paused: &Paused) -> bool {
+    state.current() == &GameState::Playing && !paused.0
+}
+
+/// Fixed-timestep game systems share this so they only tick while playing and unpaused.
+fn run_if_simulating(
+    In(input): In<ShouldRun>,
+    state: Res<State<GameState>>,
+    paused: Res<Paused>,
+) -> ShouldRun {
+    if simulating(&state, &paused) {
+        input
+    } else {
+        ShouldRun::No
+    }
+}
+
+fn spawn_centered_text(
+    commands: &mut Commands,
+    fonts: &Fonts,
+    materials: &Materials,
+    value: &str,
+) -> Entity {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: bevy::math::Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.overlay_material.clone(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    value,
+                    TextStyle {
+                        font: fonts.ui.clone(),
+                        font_size: 24.0,
+                        color: Color::WHITE,
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            });
+        })
+        .id()
+}
+
+fn toggle_pause(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut paused: ResMut<Paused>,
+    fonts: Res<Fonts>,
+    materials: Res<Materials>,
+    localization: Res<Localization>,
+    overlays: Query<Entity, With<PauseUi>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    paused.0 = !paused.0;
+    if paused.0 {
+        let overlay = spawn_centered_text(
+            &mut commands,
+            &fonts,
+            &materials,
+            localization.t("pause.title"),
+        );
+        commands.entity(overlay).insert(PauseUi);
+    } else {
+        for e in overlays.iter() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+}
+
+fn spawn_game_over(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    materials: Res<Materials>,
+    localization: Res<Localization>,
+    score: Res<Score>,
+    high_score: Res<HighScore>,
+    (trapped, drowned): (Res<Trapped>, Res<Drowned>),
+    (oxygen_mode, oxygen): (Res<OxygenMode>, Res<Oxygen>),
+    share: Res<ShareCode>,
+) {
+    let title = if trapped.0 {
+        "game_over.trapped"
+    } else if drowned.0 {
+        "game_over.drowned"
+    } else if oxygen_mode.enabled && oxygen.0 == 0. {
+        "game_over.suffocated"
+    } else {
+        "game_over.title"
+    };
+    let mut summary = format!(
+        "{}\n{}: {}\n{}: {}\n{}: {}\n",
+        localization.t(title),
+        localization.t("hud.dug"),
+        score.dug,
+        localization.t("hud.points"),
+        score.points,
+        localization.t("hud.best"),
+        high_score.0
+    );
+    if let Some(code) = &share.0 {
+        summary += &format!("{}: {}\n", localization.t("game_over.code"), code);
+    }
+    summary += localization.t("game_over.restart");
+    let screen = spawn_centered_text(&mut commands, &fonts, &materials, &summary);
+    commands.entity(screen).insert(GameOverUi);
+}
+
+/// Starts over. Leaving the end screen clears the arena and entering `Playing`
+/// spawns it afresh.
+fn on_restart(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut score: ResMut<Score>,
+    mut lives: ResMut<Lives>,
+    mut trapped: ResMut<Trapped>,
+    mut combo: ResMut<Combo>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::R) {
+        return;
+    }
+    *score = Score::default();
+    *lives = Lives::default();
+    trapped.0 = false;
+    *combo = Combo::default();
+    state.set(GameState::Playing).unwrap();
+}
+
+/// Removes everything a game put in the arena. Runs on leaving the game over
+/// and win screens rather than `Playing`, so the final board stays on show
+/// behind them. The camera, HUD and face highlights live across games.
+fn despawn_arena(
+    mut commands: Commands,
+    entities: Query<
+        Entity,
+        Or<(
+            With<Player>,
+            With<Wall>,
+            With<Enemy>,
+            With<Gem>,
+            With<Boulder>,
+            With<FogCell>,
+            With<Air>,
+            With<OxygenBar>,
+            With<TrailFade>,
+            With<Debris>,
+            With<Projectile>,
+            With<Portal>,
+            With<Exit>,
+            With<LevelClearUi>,
+            // Tuples of filters only go so far, so these share a slot
+            Or<(With<Water>, With<Trap>)>,
+        )>,
+    >,
+) {
+    for e in entities.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+fn despawn_game_over(mut commands: Commands, screens: Query<Entity, With<GameOverUi>>) {
+    for e in screens.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+fn update_score_text(
+    score: Res<Score>,
+    localization: Res<Localization>,
+    mut texts: Query<&mut Text, With<ScoreText>>,
+) {
+    if !score.is_changed() && !localization.is_changed() {
+        return;
+    }
+    let tally = |dug: u32, built: u32, gems: u32, points: u32| {
+        format!(
+            "{}: {}  {}: {}  {}: {}  {}: {}",
+            localization.t("hud.dug"),
+            dug,
+            localization.t("hud.built"),
+            built,
+            localization.t("hud.gems"),
+            gems,
+            localization.t("hud.points"),
+            points
+        )
+    };
+    let value = if score.players.len() > 1 {
+        let mut players: Vec<_> = score.players.iter().collect();
+        players.sort_by_key(|(id, _)| **id);
+        players
+            .iter()
+            .map(|(id, s)| {
+                format!(
+                    "{}{} {}",
+                    localization.t("hud.player"),
+                    id.0 + 1,
+                    tally(s.dug, s.built, s.gems, s.points)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else {
+        tally(score.dug, score.built, score.gems, score.points)
+    };
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = value.clone();
+    }
+}
+
+fn spawn_parallax_background(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+    // Cover twice the window in each direction so the edges never scroll into view
+    let columns = (window.width() * 2. / BACKGROUND_TILE_SIZE) as i32;
+    let rows = (window.height() * 2. / BACKGROUND_TILE_SIZE) as i32;
+    commands
+        .spawn()
+        .insert(ParallaxBackground)
+        .insert(Transform::from_xyz(0., 0., BACKGROUND_Z))
+        .insert(GlobalTransform::default())
+        .with_children(|parent| {
+            for x in -columns..=columns {
+                for y in -rows..=rows {
+                    // Checkerboard: only every other tile is drawn
+                    if (x + y) % 2 != 0 {
+                        continue;
+                    }
+                    parent
+                        .spawn_bundle(SpriteBundle {
+                            material: materials.background_material.clone(),
+                            sprite: Sprite::new(Vec2::new(
+                                BACKGROUND_TILE_SIZE,
+                                BACKGROUND_TILE_SIZE,
+                            )),
+                            transform: Transform::from_xyz(
+                                x as f32 * BACKGROUND_TILE_SIZE,
+                                y as f32 * BACKGROUND_TILE_SIZE,
+                                0.,
+                            ),
+                            ..Default::default()
+                        })
+                        .insert(ParallaxTile);
+                }
+            }
+        });
+}
+
+/// The interior cell closest to `preferred`, or `preferred` itself, where every
+/// cell of `footprint` stays inside the boundary and clear of `occupied`.
+fn nearest_free_cell(
+    arena: &ArenaConfig,
+    preferred: Position,
+    occupied: &[Position],
+    footprint: &Footprint,
+) -> Option<Position> {
+    let height = arena.height as i32;
+    let interior =
+        |p: &Position| p.x > 0 && p.y > 0 && p.x < arena.width as i32 - 1 && p.y < height - 1;
+    let mut cells: Vec<Position> = (1..arena.width as i32 - 1)
+        .flat_map(|x| (1..height - 1).map(move |y| Position { x, y }))
+        .filter(|p| {
+            footprint
+                .cells(p)
+                .iter()
+                .all(|cell| interior(cell) && !occupied.contains(cell))
+        })
+        .collect();
+    cells.sort_by_key(|p| (p.x - preferred.x).abs() + (p.y - preferred.y).abs());
+    cells.first().copied()
+}
+
+/// Cells taken while a game's pieces are laid out on entering `Playing`. The
+/// players, level walls and every spawner run in the same stage, so none of
+/// them can see what the others spawned; claiming cells here instead keeps
+/// them from landing on top of one another.
+#[derive(Default)]
+pub(crate) struct SpawnClaims {
+    cells: HashSet<Position>,
+    /// Where the players start this game, after any moving to a free cell.
+    starts: Vec<Position>,
+}
+
+impl SpawnClaims {
+    pub(crate) fn claim(&mut self, cell: Position) {
+        self.cells.insert(cell);
+    }
+
+    pub(crate) fn starts(&self) -> &[Position] {
+        &self.starts
+    }
+}
+
+/// Interior cells nothing has claimed yet, for spawners that run after the
+/// players to pick from.
+pub(crate) fn open_interior_cells(arena: &ArenaConfig, claims: &SpawnClaims) -> Vec<Position> {
+    let height = arena.height as i32;
+    (1..arena.width as i32 - 1)
+        .flat_map(|x| (1..height - 1).map(move |y| Position { x, y }))
+        .filter(|p| !claims.cells.contains(p))
+        .collect()
+}
+
+// Only interior cells are considered, so the boundary ring never needs checking.
+// Starting a game's claims over, this runs before everything else placed on
+// entering `Playing`.
+fn spawn_player(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    spawn: Res<PlayerSpawn>,
+    player_count: Res<PlayerCount>,
+    level: Option<Res<Level>>,
+    repeat: Res<MoveRepeat>,
+    size: Res<PlayerSize>,
+    cooldowns: Res<ActionCooldowns>,
+    materials: Res<Materials>,
+    sprites: Res<PlayerSprites>,
+    colors: Res<Assets<ColorMaterial>>,
+    mut claims: ResMut<SpawnClaims>,
+    walls: Query<&Position, With<Wall>>,
+) {
+    // Level walls are spawned in this same stage, so they aren't queryable yet
+    let mut occupied: Vec<Position> = walls.iter().copied().collect();
+    if let Some(level) = &level {
+        occupied.extend(level.walls.iter().copied());
+        occupied.extend(level.exit.map(|(exit, _)| exit));
+    }
+    *claims = SpawnClaims {
+        cells: occupied.iter().copied().collect(),
+        starts: Vec::new(),
+    };
+    let side = size.0.max(1) as i32;
+    let footprint = Footprint::square(size.0);
+    for id in (0..player_count.0).map(PlayerId) {
+        // Larger players are moved until their whole footprint fits, clear of
+        // walls and each other
+        let start = id.start(&arena, &spawn, level.as_deref());
+        let start = match nearest_free_cell(&arena, start, &occupied, &footprint) {
+            Some(start) => start,
+            None => {
+                warn!("No free cell to spawn player {} in", id.0);
+                return;
+            }
+        };
+        for cell in footprint.cells(&start) {
+            occupied.push(cell);
+            claims.claim(cell);
+        }
+        claims.starts.push(start);
+        // The palette keeps the player materials current, so tint to match
+        let material = match id.0 {
+            0 => &materials.player_material,
+            _ => &materials.second_player_material,
+        };
+        let color = colors.get(material).map_or(Color::WHITE, |m| m.color);
+        commands
+            .spawn_bundle(SpriteSheetBundle {
+                texture_atlas: sprites.atlas.clone(),
+                sprite: TextureAtlasSprite {
+                    color,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Player {
+                action: Action::Idle,
+                face_direction: Direction::Up,
+                rocks: 0,
+                chaining: false,
+                assisted: false,
+                sprinting: false,
+                tunneling: false,
+                throwing: false,
+                trapping: false,
+                stride: 1,
+                pushing: None,
+            })
+            .insert(id)
+            .insert(KeyRepeat::new(&repeat))
+            .insert(PlayerPath::default())
+            .insert(Footprint::square(size.0))
+            .insert(start)
+            .insert(Size::square(side as f32 - 0.5))
+            .insert(DigCooldown(ready_timer(cooldowns.dig)))
+            .insert(BuildCooldown(ready_timer(cooldowns.build)))
+            .insert(PlayerAnimation {
+                timer: Timer::from_seconds(PLAYER_FRAME_SECONDS, true),
+                frame: 0,
+            });
+    }
+}
+
+fn spawn_boundaries(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    level: Option<Res<Level>>,
+    materials: Res<Materials>,
+    wrap: Res<WrapMode>,
+) {
+    // A level brings its own outer walls, and a wrapping arena has no edge
+    if level.is_some() || wrap.0 {
+        return;
+    }
+    let mut boundary_positions: Vec<Position> = Vec::new();
+    for x in 0..arena.width {
+        boundary_positions.push(Position { x: x as i32, y: 0 });
+        boundary_positions.push(Position {
+            x: x as i32,
+            y: arena.height as i32 - 1,
+        })
+    }
+    for y in 1..arena.height - 1 {
+        boundary_positions.push(Position { x: 0, y: y as i32 });
+        boundary_positions.push(Position {
+            x: arena.width as i32 - 1,
+            y: y as i32,
+        });
+    }
+    while let Some(p) = boundary_positions.pop() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: materials.boundary_material.clone(),
+                sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                ..Default::default()
+            })
+            .insert(Boundary)
+            .insert(Wall)
+            .insert(p)
+            .insert(Size::square(0.8));
+    }
+}
+
+fn spawn_walls(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    practice: Res<PracticeMode>,
+    level: Option<Res<Level>>,
+    density: Res<WallDensity>,
+    treasure: Res<TreasureWalls>,
+    materials: Res<Materials>,
+    grid: Res<WorldGrid>,
+    mut rng: ResMut<GameRng>,
+    mut safe_zone: ResMut<SafeZone>,
+    interior_walls: Query<(), (With<Wall>, Without<Boundary>)>,
+    players: Query<&Position, With<Player>>,
+) {
+    if practice.0 || level.is_some() {
+        return;
+    }
+    let interior_tiles = (arena.width - 2) * (arena.height - 2);
+    let fill = interior_walls.iter().count() as f32 / interior_tiles as f32;
+    if fill >= density.target {
+        return;
+    }
+    // Do not spawn on top of an existing wall or player
+    let height = arena.height as i32;
+    let mut candidates: Vec<Position> = (1..arena.width as i32 - 1)
+        .flat_map(|x| (1..height - 1).map(move |y| Position { x, y }))
+        .filter(|p| entities_at(&grid, p).is_empty())
+        .filter(|p| !safe_zone.covers(players.iter(), p))
+        .collect();
+    // Nor anywhere that would cut a player off from cells they can reach now
+    let reachable: Vec<_> = players
+        .iter()
+        .map(|p| (*p, reachable_cells(&grid, &arena, *p, None)))
+        .collect();
+    let keeps_connected = |wall: Position| {
+        reachable.iter().all(|(start, before)| {
+            !before.contains(&wall)
+                || reachable_cells(&grid, &arena, *start, Some(wall)).len() + 1 == before.len()
+        })
+    };
+    let target_position = loop {
+        if candidates.is_empty() {
+            return;
+        }
+        let index = rng.index(candidates.len());
+        let candidate = candidates.swap_remove(index);
+        if keeps_connected(candidate) {
+            break candidate;
+        }
+    };
+    let kind = WallType::random(&mut rng);
+    let mut wall = commands.spawn_bundle(SpriteBundle {
+        material: materials.wall_for(kind),
+        sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+        ..Default::default()
+    });
+    wall.insert(Wall)
+        .insert(kind)
+        .insert(Toughness {
+            hits_remaining: kind.hits(),
+        })
+        .insert(target_position)
+        .insert(Size::square(0.8))
+        .insert(SpawnFade::new());
+    // Bedrock can't be dug, so it never holds treasure
+    if kind != WallType::Bedrock && rng.chance() < treasure.chance {
+        insert_treasure(&mut wall, &materials, &treasure, treasure.bonus);
+    }
+    safe_zone.walls_left = safe_zone.walls_left.saturating_sub(1);
+}
+
+/// Random walls spawned at the start of a game before the safe zone lifts.
+const SAFE_ZONE_WALLS: u32 = 8;
+
+/// Keeps the 3x3 block around each player clear of the first few random walls,
+/// so nobody starts out wedged in.
+struct SafeZone {
+    walls_left: u32,
+}
+
+impl Default for SafeZone {
+    fn default() -> Self {
+        Self {
+            walls_left: SAFE_ZONE_WALLS,
+        }
+    }
+}
+
+impl SafeZone {
+    fn covers<'a>(&self, mut players: impl Iterator<Item = &'a Position>, cell: &Position) -> bool {
+        self.walls_left > 0
+            && players.any(|p| (p.x - cell.x).abs() <= 1 && (p.y - cell.y).abs() <= 1)
+    }
+}
+
+fn reset_safe_zone(mut safe_zone: ResMut<SafeZone>) {
+    *safe_zone = SafeZone::default();
+}
+
+/// A minimized window reports zero size; laying sprites out against it would
+/// collapse every transform, so rendering systems skip those frames.
+fn has_area(window: &Window) -> bool {
+    window.width() > 0. && window.height() > 0.
+}
+
+/// How the arena fills a window whose shape doesn't match it.
+#[derive(PartialEq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScalingMode {
+    /// Fill the window, stretching tiles to fit.
+    Stretch,
+    /// Keep tiles square and centre the arena, leaving bars along one side.
+    Square,
+}
+
+impl Default for ScalingMode {
+    fn default() -> Self {
+        ScalingMode::Stretch
+    }
+}
+
+/// On-screen width and height of one grid cell. With the follow camera on, the
+/// window spans only the cells in view rather than the whole arena.
+fn tile_size(
+    window: &Window,
+    arena: &ArenaConfig,
+    mode: ScalingMode,
+    follow: &CameraFollow,
+) -> Vec2 {
+    let stretched = Vec2::new(
+        window.width() / follow.visible(arena.width) as f32,
+        window.height() / follow.visible(arena.height) as f32,
+    );
+    match mode {
+        ScalingMode::Stretch => stretched,
+        ScalingMode::Square => Vec2::splat(stretched.min_element()),
+    }
+}
+
+fn size_scaling(
+    windows: Res<Windows>,
+    arena: Res<ArenaConfig>,
+    mode: Res<ScalingMode>,
+    follow: Res<CameraFollow>,
+    mut q: Query<(&Size, &mut Sprite)>,
+    mut sheets: Query<(&Size, &mut Transform), With<TextureAtlasSprite>>,
+) {
+    let window = windows.get_primary().unwrap();
+    if !has_area(window) {
+        return;
+    }
+    let tile = tile_size(window, &arena, *mode, &follow);
+    for (sprite_size, mut sprite) in q.iter_mut() {
+        sprite.size = Vec2::new(sprite_size.width * tile.x, sprite_size.height * tile.y);
+    }
+    // Sheet frames draw at their size in the image, so those are scaled instead
+    for (sprite_size, mut transform) in sheets.iter_mut() {
+        let size = Vec2::new(sprite_size.width * tile.x, sprite_size.height * tile.y);
+        transform.scale = (size / PLAYER_FRAME_SIZE).extend(1.);
+    }
+}
+
+/// Shows the row of the sheet for each player's facing, stepping through the
+/// walk cycle while they move. Diagonals use the row for their sideways half.
+fn animate_player(
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut players: Query<(
+        &Player,
+        Option<&MoveAnimation>,
+        &mut PlayerAnimation,
+        &mut TextureAtlasSprite,
+    )>,
+) {
+    for (player, moving, mut animation, mut sprite) in players.iter_mut() {
+        let row = match player.face_direction {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left | Direction::UpLeft | Direction::DownLeft => 2,
+            Direction::Right | Direction::UpRight | Direction::DownRight => 3,
+        };
+        if player.action != Action::Move && moving.is_none() {
+            animation.frame = 0;
+        } else if !paused.0 && animation.timer.tick(time.delta()).just_finished() {
+            animation.frame = (animation.frame + 1) % PLAYER_FRAMES;
+        }
+        let index = row * PLAYER_FRAMES + animation.frame;
+        if sprite.index != index {
+            sprite.index = index;
+        }
+    }
+}
+
+/// Faint square over the cell a player faces, shown only while a dig would land
+/// there. It has no `Position`, so the grid never sees it.
+struct FaceHighlight(PlayerId);
+
+/// Ghost of the wall a build would place on the faced cell, tinted when
+/// something is in the way. Like `FaceHighlight` it stays off the grid.
+struct BuildPreview(PlayerId);
+
+fn spawn_face_highlights(
+    mut commands: Commands,
+    player_count: Res<PlayerCount>,
+    materials: Res<Materials>,
+) {
+    for id in (0..player_count.0).map(PlayerId) {
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: materials.highlight_material.clone(),
+                sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                visible: Visible {
+                    is_visible: false,
+                    is_transparent: true,
+                },
+                ..Default::default()
+            })
+            .insert(FaceHighlight(id))
+            .insert(Size::square(1.0));
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: materials.preview_material.clone(),
+                sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                visible: Visible {
+                    is_visible: false,
+                    is_transparent: true,
+                },
+                ..Default::default()
+            })
+            .insert(BuildPreview(id))
+            .insert(Size::square(0.8));
+    }
+}
+
+fn update_face_highlight(
+    windows: Res<Windows>,
+    arena: Res<ArenaConfig>,
+    mode: Res<ScalingMode>,
+    follow: Res<CameraFollow>,
+    wrap: Res<WrapMode>,
+    grid: Res<WorldGrid>,
+    diggable: Query<Option<&WallType>, (With<Wall>, Without<Boundary>)>,
+    players: Query<(&PlayerId, &Position, &Player, &Footprint)>,
+    mut highlights: Query<(&FaceHighlight, &mut Transform, &mut Visible)>,
+) {
+    let window = windows.get_primary().unwrap();
+    if !has_area(window) {
+        return;
+    }
+    let tile = tile_size(window, &arena, *mode, &follow);
+    for (highlight, mut transform, mut visible) in highlights.iter_mut() {
+        let target = players
+            .iter()
+            .find(|(id, _, _, _)| **id == highlight.0)
+            .map(|(_, pos, player, footprint)| {
+                footprint.ahead(pos, player.face_direction, &arena, &wrap)[0]
+            });
+        let wall = target.and_then(|target| first_wall_at(&grid, &target));
+        visible.is_visible = matches!(wall, Some(e) if is_diggable(&diggable, e));
+        if let Some(target) = target {
+            let cell = Vec2::new(target.x as f32, target.y as f32);
+            transform.translation = cell_to_world(cell, tile, &arena).extend(0.5);
+        }
+    }
+}
+
+/// Shown while a player carries rocks and faces open ground, where the action
+/// key would build rather than dig.
+fn update_build_preview(
+    windows: Res<Windows>,
+    arena: Res<ArenaConfig>,
+    mode: Res<ScalingMode>,
+    follow: Res<CameraFollow>,
+    wrap: Res<WrapMode>,
+    grid: Res<WorldGrid>,
+    materials: Res<Materials>,
+    blockers: Query<(), Or<(With<Boulder>, With<Player>)>>,
+    players: Query<(&PlayerId, &Position, &Player, &Footprint)>,
+    mut previews: Query<(
+        &BuildPreview,
+        &mut Transform,
+        &mut Visible,
+        &mut Handle<ColorMaterial>,
+    )>,
+) {
+    let window = windows.get_primary().unwrap();
+    if !has_area(window) {
+        return;
+    }
+    let tile = tile_size(window, &arena, *mode, &follow);
+    for (preview, mut transform, mut visible, mut material) in previews.iter_mut() {
+        let target = players
+            .iter()
+            .find(|(id, _, player, _)| **id == preview.0 && player.rocks > 0)
+            .map(|(_, pos, player, footprint)| {
+                footprint.ahead(pos, player.face_direction, &arena, &wrap)[0]
+            })
+            .filter(|target| first_wall_at(&grid, target).is_none());
+        visible.is_visible = target.is_some();
+        if let Some(target) = target {
+            let blocked = !arena.contains(&target)
+                || entities_at(&grid, &target)
+                    .iter()
+                    .any(|e| blockers.get(*e).is_ok());
+            let tint = if blocked {
+                &materials.blocked_preview_material
+            } else {
+                &materials.preview_material
+            };
+            if *material != *tint {
+                *material = tint.clone();
+            }
+            let cell = Vec2::new(target.x as f32, target.y as f32);
+            transform.translation = cell_to_world(cell, tile, &arena).extend(0.5);
+        }
+    }
+}
+
+/// Slides a sprite between cells over one action tick. `from` and `to` are in
+/// grid cells, not pixels, so the layout still follows window resizes.
+struct MoveAnimation(Tween<Vec3>);
+
+impl MoveAnimation {
+    fn new(from: Position, to: Position, seconds: f32) -> Self {
+        Self(Tween::new(
+            Vec3::new(from.x as f32, from.y as f32, 0.),
+            Vec3::new(to.x as f32, to.y as f32, 0.),
+            seconds,
+            Easing::Linear,
+        ))
+    }
+
+    fn current(&self) -> Vec3 {
+        self.0.value()
+    }
+}
+
+fn animate_moves(
+    mut commands: Commands,
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    mut q: Query<(Entity, &mut MoveAnimation)>,
+) {
+    let delta = scale.delta(&time) as f32;
+    for (e, mut animation) in q.iter_mut() {
+        animation.0.advance(delta);
+        if animation.0.finished() {
+            commands.entity(e).remove::<MoveAnimation>();
+        }
+    }
+}
+
+/// Where an entity stood at the last two action ticks, so rendering can blend
+/// between them instead of jumping once per tick.
+struct SimState {
+    previous: Position,
+    current: Position,
+}
+
+impl SimState {
+    /// The cell to draw at, `progress` of the way through the current tick.
+    /// Anything further than a sprint in one tick was a wrap or a respawn, and
+    /// jumps rather than sliding across the arena.
+    fn blended(&self, progress: f32) -> Vec2 {
+        let from = Vec2::new(self.previous.x as f32, self.previous.y as f32);
+        let to = Vec2::new(self.current.x as f32, self.current.y as f32);
+        if (to - from).abs().max_element() > 2. {
+            return to;
+        }
+        from.lerp(to, progress)
+    }
+}
+
+/// Runs last in each action tick, once everything has moved.
+fn track_sim_state(
+    mut commands: Commands,
+    mut q: Query<(Entity, &Position, Option<&mut SimState>)>,
+) {
+    for (e, pos, state) in q.iter_mut() {
+        match state {
+            Some(mut state) => {
+                state.previous = state.current;
+                state.current = *pos;
+            }
+            None => {
+                commands.entity(e).insert(SimState {
+                    previous: *pos,
+                    current: *pos,
+                });
+            }
+        }
+    }
+}
+
+fn position_translation(
+    windows: Res<Windows>,
+    arena: Res<ArenaConfig>,
+    mode: Res<ScalingMode>,
+    follow: Res<CameraFollow>,
+    timesteps: Res<Timesteps>,
+    action_clock: Res<ActionClock>,
+    state: Res<State<GameState>>,
+    paused: Res<Paused>,
+    mut q: Query<(
+        &Position,
+        Option<&MoveAnimation>,
+        Option<&SimState>,
+        Option<&Footprint>,
+        &mut Transform,
+    )>,
+) {
+    let window = windows.get_primary().unwrap();
+    if !has_area(window) {
+        return;
+    }
+    let tile = tile_size(window, &arena, *mode, &follow);
+    // The tick clock keeps running while the simulation is stopped, so draw
+    // positions as they are then
+    let progress = Some(action_clock.0.progress(timesteps.action_step()))
+        .filter(|_| simulating(&state, &paused));
+    for (pos, animation, sim, footprint, mut transform) in q.iter_mut() {
+        let cell = match (animation, sim, progress) {
+            (Some(animation), _, _) => animation.current().truncate(),
+            (None, Some(sim), Some(progress)) => sim.blended(progress),
+            _ => Vec2::new(pos.x as f32, pos.y as f32),
+        } + footprint.map_or(Vec2::ZERO, Footprint::centre);
+        transform.translation = cell_to_world(cell, tile, &arena).extend(0.0);
+    }
+}
+
+/// Where the centre of a cell is drawn. The arena is centred on the origin, so
+/// offset by half its on-screen size.
+fn cell_to_world(cell: Vec2, tile: Vec2, arena: &ArenaConfig) -> Vec2 {
+    fn convert(pos: f32, tile_size: f32, bound_game: f32) -> f32 {
+        pos * tile_size - (bound_game * tile_size / 2.) + (tile_size / 2.)
+    }
+    Vec2::new(
+        convert(cell.x, tile.x, arena.width as f32),
+        convert(cell.y, tile.y, arena.height as f32),
+    )
+}
+
+/// The cell drawn at `world`, undoing `cell_to_world`.
+fn world_to_cell(world: Vec2, tile: Vec2, arena: &ArenaConfig) -> Position {
+    fn convert(pos: f32, tile_size: f32, bound_game: f32) -> i32 {
+        ((pos + bound_game * tile_size / 2. - tile_size / 2.) / tile_size).round() as i32
+    }
+    Position {
+        x: convert(world.x, tile.x, arena.width as f32),
+        y: convert(world.y, tile.y, arena.height as f32),
+    }
+}
+
+/// The arena cell under the mouse, if the cursor is over the window and the arena.
+fn cursor_cell(
+    window: &Window,
+    (camera, projection): (&Transform, &OrthographicProjection),
+    arena: &ArenaConfig,
+    mode: ScalingMode,
+    follow: &CameraFollow,
+) -> Option<Position> {
+    if !has_area(window) {
+        return None;
+    }
+    // Cursor coordinates start at the window's bottom-left corner, the world's
+    // at the camera, and zooming scales the distance between them
+    let cursor = window.cursor_position()?;
+    let offset = cursor - Vec2::new(window.width(), window.height()) / 2.;
+    let world = offset * projection.scale + camera.translation.truncate();
+    let cell = world_to_cell(world, tile_size(window, arena, mode, follow), arena);
+    Some(cell).filter(|cell| arena.contains(cell))
+}
+
+fn parallax(
+    config: Res<ParallaxConfig>,
+    cameras: Query<&Transform, (With<MainCamera>, Without<ParallaxBackground>)>,
+    mut backgrounds: Query<&mut Transform, With<ParallaxBackground>>,
+    mut tiles: Query<&mut Visible, With<ParallaxTile>>,
+) {
+    for mut visible in tiles.iter_mut() {
+        visible.is_visible = config.enabled;
+    }
+    if !config.enabled {
+        return;
+    }
+    if let Some(camera) = cameras.iter().next() {
+        for mut transform in backgrounds.iter_mut() {
+            // Trailing the camera by part of its offset makes the layer appear to scroll slower
+            transform.translation.x = camera.translation.x * (1. - config.factor);
+            transform.translation.y = camera.translation.y * (1. - config.factor);
+        }
+    }
+}
+
+/// When set, holding the action key after a dig keeps tunnelling in a straight line:
+/// each action tick the player steps into the cleared cell and digs the next one,
+/// stopping at the first cell that isn't a diggable wall or when the key is released.
+/// Chained digs run at the normal action rate, one cell per tick, or slower if
+/// the dig cooldown is longer.
+#[derive(Default, Deserialize)]
+pub struct ChainDig(pub bool);
+
+/// Accessibility setting: while enabled, Tab sends the player walking to the nearest
+/// diggable wall, one step per action tick, and digs it on arrival.
+/// Any other key press hands control back.
+#[derive(Default, Deserialize)]
+pub struct AutoPlayAssist(pub bool);
+
+/// Developer overlay with FPS and wall count, toggled with F3.
+#[derive(Default)]
+pub struct DebugOverlay(pub bool);
+
+/// Source of every random choice in a game. Seeding it makes wall layouts and
+/// spawns repeat from run to run, given the same play.
+pub struct GameRng(StdRng);
+
+impl GameRng {
+    /// Seeds from entropy when no seed is given.
+    pub fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => Self(StdRng::seed_from_u64(seed)),
+            None => Self(StdRng::from_entropy()),
+        }
+    }
+
+    /// A uniform index into `len` items; `len` must not be zero.
+    fn index(&mut self, len: usize) -> usize {
+        self.0.gen_range(0..len)
+    }
+
+    /// A uniform value in `[0, 1)`.
+    fn chance(&mut self) -> f32 {
+        self.0.gen()
+    }
+}
+
+/// Share of interior tiles random walls may cover; spawning pauses at this fill
+/// and picks up again as walls are dug.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WallDensity {
+    pub target: f32,
+}
+
+impl Default for WallDensity {
+    fn default() -> Self {
+        Self { target: 0.4 }
+    }
+}
+
+/// Random walls that hide a score bonus, found by digging them out.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TreasureWalls {
+    /// Share of random walls holding treasure.
+    pub chance: f32,
+    pub bonus: u32,
+    /// Marks treasure walls with a small glint rather than keeping them
+    /// looking like any other wall.
+    pub tell: bool,
+}
+
+impl Default for TreasureWalls {
+    fn default() -> Self {
+        Self {
+            chance: 0.05,
+            bonus: 25,
+            tell: false,
+        }
+    }
+}
+
+/// Most rocks a player can carry at once.
+#[derive(Deserialize)]
+pub struct RockCapacity(pub u32);
+
+impl Default for RockCapacity {
+    fn default() -> Self {
+        Self(3)
+    }
+}
+
+/// Least seconds between one player's digs, and between their builds, however
+/// fast the action ticks run. Zero leaves an action unlimited.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ActionCooldowns {
+    pub dig: f32,
+    pub build: f32,
+}
+
+impl Default for ActionCooldowns {
+    fn default() -> Self {
+        Self {
+            dig: 0.25,
+            build: 0.0,
+        }
+    }
+}
+
+impl ActionCooldowns {
+    pub(crate) fn validated(self) -> Self {
+        let default = Self::default();
+        Self {
+            dig: checked_seconds("cooldowns.dig", self.dig, default.dig),
+            build: checked_seconds("cooldowns.build", self.build, default.build),
+        }
+    }
+}
+
+/// Counts down until the player may dig again.
+struct DigCooldown(Timer);
+
+/// Counts down until the player may build again.
+struct BuildCooldown(Timer);
+
+/// Starts out elapsed, so the first action of a game isn't held back.
+fn ready_timer(seconds: f32) -> Timer {
+    let mut timer = Timer::from_seconds(seconds, false);
+    timer.tick(timer.duration());
+    timer
+}
+
+/// Restarts a cooldown after its action. Zero-length ones are left elapsed,
+/// since a reset timer only finishes again on its next tick.
+fn restart_cooldown(timer: &mut Timer) {
+    if !timer.duration().is_zero() {
+        timer.reset();
+    }
+}
+
+fn tick_action_cooldowns(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut players: Query<(&mut DigCooldown, &mut BuildCooldown)>,
+) {
+    if paused.0 {
+        return;
+    }
+    for (mut dig, mut build) in players.iter_mut() {
+        dig.0.tick(scale.elapsed(&time));
+        build.0.tick(scale.elapsed(&time));
+    }
+}
+
+/// Optional cap on walls built per game. A level's own budget takes precedence
+/// over `budget`, and with neither, building is only limited by rocks.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct BuildLimit {
+    pub budget: Option<u32>,
+    /// Whether each wall dug earns back a build.
+    pub dig_refunds: bool,
+}
+
+/// Builds left this game, only present while a budget applies.
+struct BuildBudget(u32);
+
+fn start_build_budget(mut commands: Commands, limit: Res<BuildLimit>, level: Option<Res<Level>>) {
+    match level.and_then(|level| level.budget).or(limit.budget) {
+        Some(budget) => commands.insert_resource(BuildBudget(budget)),
+        None => commands.remove_resource::<BuildBudget>(),
+    }
+}
+
+/// Sandbox for trying out build patterns: rocks are unlimited, so the action key
+/// digs when facing a wall and builds otherwise. Random walls and enemies stop
+/// spawning, a timed game's clock is left off, and Backspace clears every
+/// interior wall and returns players to the start.
+#[derive(Default, Deserialize)]
+pub struct PracticeMode(pub bool);
+
+/// Most digs and builds practice mode remembers for undo.
+const ACTION_HISTORY_LIMIT: usize = 50;
+
+/// A dig or build practice mode can take back.
+struct ActionRecord {
+    kind: Action,
+    position: Position,
+    player: PlayerId,
+    /// What a dug wall was made of, so undo brings back the same one.
+    wall: Option<WallType>,
+    /// The player's rocks before the action.
+    rocks: u32,
+}
+
+/// Recent practice digs and builds, newest last. Z undoes the newest; U is
+/// already a diagonal move.
+#[derive(Default)]
+struct ActionHistory(VecDeque<ActionRecord>);
+
+impl ActionHistory {
+    fn push(&mut self, record: ActionRecord) {
+        if self.0.len() == ACTION_HISTORY_LIMIT {
+            self.0.pop_front();
+        }
+        self.0.push_back(record);
+    }
+}
+
+/// Toroidal arena: players leaving one edge come back in at the opposite one,
+/// and there is no boundary ring.
+#[derive(Default, Deserialize)]
+pub struct WrapMode(pub bool);
+
+/// Timed challenge: while enabled, the game ends when `seconds` run out.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TimedMode {
+    pub enabled: bool,
+    pub seconds: f32,
+}
+
+impl Default for TimedMode {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            seconds: 60.0,
+        }
+    }
+}
+
+impl TimedMode {
+    pub(crate) fn validated(self) -> Self {
+        Self {
+            seconds: checked_seconds("timed.seconds", self.seconds, Self::default().seconds),
+            ..self
+        }
+    }
+}
+
+/// Clock for the current timed game, only present while `TimedMode` is enabled.
+struct GameTimer(Timer);
+
+fn start_game_timer(mut commands: Commands, mode: Res<TimedMode>, practice: Res<PracticeMode>) {
+    if mode.enabled && !practice.0 {
+        commands.insert_resource(GameTimer(Timer::from_seconds(mode.seconds, false)));
+    } else {
+        commands.remove_resource::<GameTimer>();
+    }
+}
+
+fn tick_game_timer(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    timer: Option<ResMut<GameTimer>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let mut timer = match timer {
+        Some(timer) => timer,
+        None => return,
+    };
+    if paused.0 {
+        return;
+    }
+    if timer.0.tick(scale.elapsed(&time)).just_finished() {
+        state.overwrite_set(GameState::GameOver).unwrap();
+    }
+}
+
+/// Unpaused seconds spent in the current game.
+#[derive(Default)]
+struct PlayTime(f32);
+
+fn reset_play_time(mut play_time: ResMut<PlayTime>) {
+    play_time.0 = 0.;
+}
+
+/// Seconds after a dig that the next one keeps the combo going.
+const COMBO_SECONDS: f32 = 1.5;
+
+/// Digs made in quick succession. Each one scores `count` points.
+struct Combo {
+    count: u32,
+    timer: Timer,
+}
+
+impl Default for Combo {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            timer: Timer::from_seconds(COMBO_SECONDS, false),
+        }
+    }
+}
+
+impl Combo {
+    /// Counts a dig and returns the points it is worth.
+    fn extend(&mut self) -> u32 {
+        self.count += 1;
+        self.timer.reset();
+        self.count
+    }
+}
+
+fn tick_combo(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut combo: ResMut<Combo>,
+) {
+    if paused.0 || combo.count == 0 {
+        return;
+    }
+    if combo.timer.tick(scale.elapsed(&time)).finished() {
+        combo.count = 0;
+    }
+}
+
+fn tick_play_time(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut play_time: ResMut<PlayTime>,
+) {
+    if !paused.0 {
+        play_time.0 += scale.delta(&time) as f32;
+    }
+}
+
+/// Tick rates of the fixed-step systems, read every frame so difficulty can be
+/// tuned while the game runs.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Timesteps {
+    /// Player action ticks per second.
+    pub action_hz: f64,
+    /// Random wall spawns per second.
+    pub spawn_hz: f64,
+}
+
+impl Default for Timesteps {
+    fn default() -> Self {
+        Self {
+            action_hz: 20.0,
+            spawn_hz: 1.0,
+        }
+    }
+}
+
+impl Timesteps {
+    pub(crate) fn validated(self) -> Self {
+        let default = Self::default();
+        Self {
+            action_hz: checked_rate("action_hz", self.action_hz, default.action_hz),
+            spawn_hz: checked_rate("spawn_hz", self.spawn_hz, default.spawn_hz),
+        }
+    }
+
+    fn action_step(&self) -> f64 {
+        1. / self.action_hz
+    }
+
+    fn spawn_step(&self) -> f64 {
+        1. / self.spawn_hz
+    }
+}
+
+/// Time banked towards a fixed-rate system set's next step. Like `FixedTimestep`
+/// it catches up on missed steps, but the step length is passed in each frame.
+#[derive(Default)]
+struct StepClock {
+    accumulator: f64,
+    looping: bool,
+}
+
+impl StepClock {
+    fn update(&mut self, delta: f64, step: f64) -> ShouldRun {
+        if !self.looping {
+            self.accumulator += delta;
+        }
+        if self.accumulator >= step {
+            self.accumulator -= step;
+            self.looping = true;
+            ShouldRun::YesAndCheckAgain
+        } else {
+            self.looping = false;
+            ShouldRun::No
+        }
+    }
+
+    /// How far into the next step the clock is, from 0 to 1.
+    fn progress(&self, step: f64) -> f32 {
+        (self.accumulator / step).min(1.) as f32
+    }
+}
+
+/// Kept as a resource so rendering can read how far through a tick it is.
+#[derive(Default)]
+struct ActionClock(StepClock);
+
+fn action_tick(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    timesteps: Res<Timesteps>,
+    mut clock: ResMut<ActionClock>,
+) -> ShouldRun {
+    clock.0.update(scale.delta(&time), timesteps.action_step())
+}
+
+fn spawn_tick(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    timesteps: Res<Timesteps>,
+    mut clock: Local<StepClock>,
+) -> ShouldRun {
+    clock.update(scale.delta(&time), timesteps.spawn_step())
+}
+
+/// Speed of the simulation relative to real time. Rendering keeps its frame
+/// rate; the fixed steps, the slides between them and every gameplay timer
+/// stretch out together.
+pub struct TimeScale {
+    pub rate: f32,
+    /// Stands in for the real frame time when set, so a headless run steps
+    /// the same way every time however fast it goes.
+    pub fixed_frame: Option<Duration>,
+}
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        Self {
+            rate: 1.0,
+            fixed_frame: None,
+        }
+    }
+}
+
+impl TimeScale {
+    /// Time since the last frame, before scaling.
+    fn frame(&self, time: &Time) -> Duration {
+        self.fixed_frame.unwrap_or_else(|| time.delta())
+    }
+
+    /// Simulated time since the last frame.
+    fn elapsed(&self, time: &Time) -> Duration {
+        self.frame(time).mul_f32(self.rate)
+    }
+
+    /// Simulated seconds since the last frame.
+    fn delta(&self, time: &Time) -> f64 {
+        self.elapsed(time).as_secs_f64()
+    }
+}
+
+/// Time scale while slow motion is toggled on.
+const SLOW_MOTION_SCALE: f32 = 0.25;
+
+fn toggle_slow_motion(keyboard_input: Res<Input<KeyCode>>, mut scale: ResMut<TimeScale>) {
+    if keyboard_input.just_pressed(KeyCode::T) {
+        scale.rate = if scale.rate < 1.0 {
+            1.0
+        } else {
+            SLOW_MOTION_SCALE
+        };
+    }
+}
+
+/// Holding a direction key steps once, waits `delay` seconds, then steps
+/// every `rate` seconds until it's let go, like key repeat in a text editor.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct MoveRepeat {
+    pub delay: f32,
+    pub rate: f32,
+}
+
+impl Default for MoveRepeat {
+    fn default() -> Self {
+        Self {
+            delay: 0.2,
+            rate: 0.08,
+        }
+    }
+}
+
+impl MoveRepeat {
+    pub(crate) fn validated(self) -> Self {
+        let default = Self::default();
+        Self {
+            delay: checked_seconds("move_repeat.delay", self.delay, default.delay),
+            rate: checked_seconds("move_repeat.rate", self.rate, default.rate),
+        }
+    }
+}
+
+/// Repeat state for the direction key a player pressed last.
+struct KeyRepeat {
+    delay: Timer,
+    rate: Timer,
+    active_key: Option<KeyCode>,
+}
+
+impl KeyRepeat {
+    fn new(repeat: &MoveRepeat) -> Self {
+        Self {
+            delay: Timer::from_seconds(repeat.delay, false),
+            rate: Timer::from_seconds(repeat.rate, true),
+            active_key: None,
+        }
+    }
+
+    /// A fresh press steps straight away, so the repeat starts over from it.
+    fn press(&mut self, key: KeyCode) {
+        self.active_key = Some(key);
+        self.delay.reset();
+        self.rate.reset();
+    }
+
+    /// The active key, if it's still held and due another step. Letting go
+    /// of it stops the repeat.
+    fn tick(&mut self, delta: Duration, keyboard_input: &Input<KeyCode>) -> Option<KeyCode> {
+        let key = match self.active_key {
+            Some(key) if keyboard_input.pressed(key) => key,
+            _ => {
+                self.active_key = None;
+                return None;
+            }
+        };
+        let due = if self.delay.finished() {
+            self.rate.tick(delta).just_finished()
+        } else {
+            self.delay.tick(delta).just_finished()
+        };
+        Some(key).filter(|_| due)
+    }
+}
+
+/// How direction keys drive the player.
+#[derive(PartialEq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MovementStyle {
+    /// One cell per key press.
+    StepStop,
+    /// Keep moving until blocked by a wall; pressing the opposite direction brakes.
+    Continuous,
+}
+
+impl Default for MovementStyle {
+    fn default() -> Self {
+        MovementStyle::StepStop
+    }
+}
+
+pub struct PlayerActionPlugin;
+
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub enum PlayerActions {
+    Input,
+    GamepadInput,
+    Grid,
+    Overlaps,
+    Assist,
+    Path,
+    ChainDig,
+    InputValidation,
+    MoveAction,
+    Slides,
+    Gravity,
+    PortalTravel,
+    DigAction,
+    BuildAction,
+    Projectiles,
+    ThrowAction,
+    TrapAction,
+    SimState,
+}
+
+/// The rest of the world's turn once the players have acted, taken in this
+/// order so a seed always plays out the same way.
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub enum WorldSteps {
+    Walls,
+    Stun,
+    Enemies,
+    Water,
+    Contact,
+    Traps,
+    Gems,
+    Drowning,
+    Air,
+    Oxygen,
+    Trapped,
+    Exit,
+    Clear,
+    Timer,
+}
+
+impl Plugin for PlayerActionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(
+                    player_input
+                        .system()
+                        .label(PlayerActions::Input)
+                        .before(PlayerActions::InputValidation),
+                )
+                .with_system(
+                    gamepad_input
+                        .system()
+                        .label(PlayerActions::GamepadInput)
+                        .after(PlayerActions::Input)
+                        .before(PlayerActions::InputValidation),
+                )
+                .with_system(path_to.system())
+                .with_system(practice_reset.system())
+                .with_system(practice_undo.system())
+                .with_system(toggle_pause.system()),
+        )
+        .add_system_set(
+            SystemSet::new()
+                .with_run_criteria(action_tick.system().chain(run_if_simulating.system()))
+                .with_system(
+                    update_world_grid
+                        .system()
+                        .label(PlayerActions::Grid)
+                        .after(PlayerActions::GamepadInput)
+                        // The last of what's placed on entering `Playing`
+                        .after("sources")
+                        .before(PlayerActions::Overlaps),
+                )
+                .with_system(
+                    detect_overlaps
+                        .system()
+                        .label(PlayerActions::Overlaps)
+                        .before(PlayerActions::Assist),
+                )
+                .with_system(
+                    auto_play_assist
+                        .system()
+                        .label(PlayerActions::Assist)
+                        .before(PlayerActions::Path),
+                )
+                .with_system(
+                    follow_path
+                        .system()
+                        .label(PlayerActions::Path)
+                        .before(PlayerActions::ChainDig),
+                )
+                .with_system(
+                    player_chain_dig
+                        .system()
+                        .label(PlayerActions::ChainDig)
+                        .before(PlayerActions::InputValidation),
+                )
+                .with_system(
+                    validate_player_action
+                        .system()
+                        .label(PlayerActions::InputValidation)
+                        .before(PlayerActions::MoveAction),
+                )
+                .with_system(
+                    player_move_action
+                        .system()
+                        .label(PlayerActions::MoveAction)
+                        .before(PlayerActions::Slides),
+                )
+                .with_system(
+                    slide_system
+                        .system()
+                        .label(PlayerActions::Slides)
+                        .before(PlayerActions::Gravity),
+                )
+                .with_system(
+                    player_gravity
+                        .system()
+                        .label(PlayerActions::Gravity)
+                        .before(PlayerActions::PortalTravel),
+                )
+                .with_system(
+                    portal_travel
+                        .system()
+                        .label(PlayerActions::PortalTravel)
+                        .before(PlayerActions::DigAction),
+                )
+                .with_system(
+                    player_dig_action
+                        .system()
+                        .label(PlayerActions::DigAction)
+                        .before(PlayerActions::BuildAction),
+                )
+                .with_system(
+                    player_build_action
+                        .system()
+                        .label(PlayerActions::BuildAction)
+                        .before(PlayerActions::Projectiles),
+                )
+                .with_system(
+                    move_projectiles
+                        .system()
+                        .label(PlayerActions::Projectiles)
+                        .before(PlayerActions::ThrowAction),
+                )
+                .with_system(
+                    player_throw_action
+                        .system()
+                        .label(PlayerActions::ThrowAction)
+                        .before(PlayerActions::TrapAction),
+                )
+                .with_system(
+                    player_trap_action
+                        .system()
+                        .label(PlayerActions::TrapAction)
+                        .before(PlayerActions::SimState),
+                )
+                .with_system(track_sim_state.system().label(PlayerActions::SimState)),
+        )
+        .add_system_to_stage(CoreStage::PreUpdate, update_world_grid.system())
+        .init_resource::<WorldGrid>();
+    }
+}
+
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub enum PlayerMovement {
+    Input,
+    Movement,
+}
+
+fn steer(p: &mut Player, direction: Direction, style: MovementStyle) {
+    if style == MovementStyle::Continuous
+        && p.action == Action::Move
+        && p.face_direction == direction.opposite()
+    {
+        p.action = Action::Idle;
+        return;
+    }
+    p.face_direction = direction;
+    p.action = Action::Move;
+}
+
+fn any_just_pressed(keyboard_input: &Input<KeyCode>, keys: &[KeyCode]) -> bool {
+    keys.iter().any(|k| keyboard_input.just_pressed(*k))
+}
+
+fn player_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    assist: Res<AutoPlayAssist>,
+    style: Res<MovementStyle>,
+    bindings: Res<KeyBindings>,
+    camera_mode: Res<CameraMode>,
+    mut player_positions: Query<(&PlayerId, &mut Player, &mut KeyRepeat, &mut PlayerPath)>,
+) {
+    // The free camera has the arrow keys; players carry on as they were
+    if paused.0 || *camera_mode == CameraMode::Free {
+        return;
+    }
+    for (id, mut p, mut key_repeat, mut path) in player_positions.iter_mut() {
+        let controls = bindings.for_player(id);
+        let manual = [
+            &controls.up,
+            &controls.down,
+            &controls.left,
+            &controls.right,
+        ]
+        .iter()
+        .any(|keys| any_just_pressed(&keyboard_input, keys))
+            || controls
+                .diagonals
+                .iter()
+                .any(|(key, _)| keyboard_input.just_pressed(*key))
+            || keyboard_input.just_pressed(controls.action);
+        if p.assisted && manual {
+            p.assisted = false;
+        }
+        if manual {
+            path.0.clear();
+        }
+        // The assist is bound to the primary player only
+        if id.0 == 0 && assist.0 && keyboard_input.just_pressed(KeyCode::Tab) {
+            p.assisted = !p.assisted;
+        }
+        // Continuous movement keeps going by itself, so only step-by-step repeats held keys
+        let repeated = match *style {
+            MovementStyle::StepStop => key_repeat.tick(time.delta(), &keyboard_input),
+            MovementStyle::Continuous => None,
+        };
+        let directions = [
+            (&controls.down[..], Direction::Down),
+            (&controls.up[..], Direction::Up),
+            (&controls.right[..], Direction::Right),
+            (&controls.left[..], Direction::Left),
+        ];
+        let diagonals = controls
+            .diagonals
+            .iter()
+            .map(|(key, direction)| (std::slice::from_ref(key), *direction));
+        // Sprint already claims Shift, so turning in place has a key of its own
+        let turning = controls
+            .turn
+            .map_or(false, |key| keyboard_input.pressed(key));
+        for (keys, direction) in directions.iter().copied().chain(diagonals) {
+            if turning {
+                if any_just_pressed(&keyboard_input, keys) {
+                    p.face_direction = direction;
+                    if p.action == Action::Move {
+                        p.action = Action::Idle;
+                    }
+                }
+                continue;
+            }
+            let pressed = keys.iter().find(|k| keyboard_input.just_pressed(**k));
+            if let Some(key) = pressed {
+                key_repeat.press(*key);
+            }
+            if pressed.is_some() || repeated.map_or(false, |key| keys.contains(&key)) {
+                steer(&mut p, direction, *style);
+            }
+        }
+        if p.action == Action::Move {
+            p.sprinting = keyboard_input.pressed(controls.sprint);
+        }
+        if keyboard_input.just_pressed(controls.action) {
+            use_action(&mut p);
+            p.tunneling = keyboard_input.pressed(controls.sprint);
+        }
+        if controls
+            .throw
+            .map_or(false, |key| keyboard_input.just_pressed(key))
+        {
+            p.throwing = true;
+        }
+        if controls
+            .trap
+            .map_or(false, |key| keyboard_input.just_pressed(key))
+        {
+            p.trapping = true;
+        }
+    }
+}
+
+/// The action button digs empty-handed and builds while carrying rocks;
+/// validation swaps one for the other to suit the cell being faced.
+fn use_action(p: &mut Player) {
+    p.action = if p.rocks > 0 {
+        Action::Build
+    } else {
+        Action::Dig
+    };
+}
+
+/// Safety net for spawn races and bad builds: when two solid entities share a
+/// cell, the last player or boulder among them is nudged to a free neighbour,
+/// and a wall doubled up on another is removed. Debug builds also log the
+/// overlap. An enemy, gem, air, water, trap or thrown rock sharing a cell is a
+/// catch, a pickup or a hit, not an overlap, so those don't count towards it.
+fn detect_overlaps(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    grid: Res<WorldGrid>,
+    mut movable: Query<&mut Position, Or<(With<Player>, With<Boulder>)>>,
+    interior_walls: Query<(), (With<Wall>, Without<Boundary>)>,
+    passable: Query<
+        (),
+        Or<(
+            With<Enemy>,
+            With<Gem>,
+            With<Air>,
+            With<Projectile>,
+            With<Portal>,
+            With<Exit>,
+            With<Water>,
+            With<Trap>,
+        )>,
+    >,
+) {
+    let mut claimed: Vec<Position> = Vec::new();
+    for (cell, entities) in crowded_cells(&grid) {
+        let solid: Vec<Entity> = entities
+            .into_iter()
+            .filter(|e| passable.get(*e).is_err())
+            .collect();
+        if solid.len() < 2 {
+            continue;
+        }
+        if cfg!(debug_assertions) {
+            warn!("{} entities overlap at {:?}", solid.len(), cell);
+        }
+        let nudged = solid
+            .iter()
+            .rev()
+            .copied()
+            .find(|e| movable.get_mut(*e).is_ok());
+        let e = match nudged {
+            Some(e) => e,
+            None => {
+                if let Some(wall) = solid.iter().rev().find(|e| interior_walls.get(**e).is_ok()) {
+                    commands.entity(*wall).despawn_recursive();
+                }
+                continue;
+            }
+        };
+        let free = adjacent_direction(&cell, |p| {
+            arena.contains(p) && entities_at(&grid, p).is_empty() && !claimed.contains(p)
+        });
+        if let Some(direction) = free {
+            let target = cell.translated(direction);
+            if let Ok(mut pos) = movable.get_mut(e) {
+                *pos = target;
+            }
+            claimed.push(target);
+        }
+    }
+}
+
+fn auto_play_assist(
+    assist: Res<AutoPlayAssist>,
+    arena: Res<ArenaConfig>,
+    grid: Res<WorldGrid>,
+    mut players: Query<(&Position, &mut Player)>,
+    diggable: Query<Option<&WallType>, (With<Wall>, Without<Boundary>)>,
+) {
+    let diggable_at =
+        |pos: &Position| first_wall_at(&grid, pos).map_or(false, |e| is_diggable(&diggable, e));
+    for (pos, mut player) in players.iter_mut() {
+        if !player.assisted {
+            continue;
+        }
+        if !assist.0 {
+            player.assisted = false;
+            continue;
+        }
+        if let Some(direction) = adjacent_direction(pos, diggable_at) {
+            player.face_direction = direction;
+            player.action = Action::Dig;
+            player.assisted = false;
+            continue;
+        }
+        match find_path(&grid, &arena, *pos, |p| {
+            adjacent_direction(p, diggable_at).is_some()
+        })
+        .and_then(|path| path.first().copied())
+        {
+            Some(direction) => {
+                player.face_direction = direction;
+                player.action = Action::Move;
+            }
+            None => player.assisted = false,
+        }
+    }
+}
+
+/// Moves still to make on the way to a clicked cell, one per action tick.
+#[derive(Default)]
+struct PlayerPath(VecDeque<Direction>);
+
+/// Clicking an open cell walks the primary player there around any walls.
+fn path_to(
+    mouse_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    paused: Res<Paused>,
+    arena: Res<ArenaConfig>,
+    mode: Res<ScalingMode>,
+    follow: Res<CameraFollow>,
+    grid: Res<WorldGrid>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<MainCamera>>,
+    mut players: Query<(&PlayerId, &Position, &mut PlayerPath)>,
+) {
+    if paused.0 || !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let window = windows.get_primary().unwrap();
+    let target = match cameras.iter().next() {
+        Some(camera) => cursor_cell(window, camera, &arena, *mode, &follow),
+        None => return,
+    };
+    let target = match target {
+        Some(target) => target,
+        None => return,
+    };
+    for (id, pos, mut path) in players.iter_mut() {
+        if id.0 != 0 {
+            continue;
+        }
+        path.0 = find_path(&grid, &arena, *pos, |p| *p == target)
+            .map(VecDeque::from)
+            .unwrap_or_default();
+    }
+}
+
+/// Feeds the next step of each player's path into the move action. A wall
+/// built or grown across the route since the click cancels the rest of it.
+fn follow_path(
+    arena: Res<ArenaConfig>,
+    grid: Res<WorldGrid>,
+    mut players: Query<(&Position, &mut Player, &mut PlayerPath)>,
+) {
+    for (pos, mut player, mut path) in players.iter_mut() {
+        let direction = match path.0.pop_front() {
+            Some(direction) => direction,
+            None => continue,
+        };
+        let next = pos.translated(direction);
+        if !arena.contains(&next) || first_wall_at(&grid, &next).is_some() {
+            path.0.clear();
+            continue;
+        }
+        player.face_direction = direction;
+        player.action = Action::Move;
+    }
+}
+
+fn player_chain_dig(
+    chain_dig: Res<ChainDig>,
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    bindings: Res<KeyBindings>,
+    arena: Res<ArenaConfig>,
+    wrap: Res<WrapMode>,
+    grid: Res<WorldGrid>,
+    mut players: Query<(Entity, &PlayerId, &mut Position, &mut Player)>,
+    diggable: Query<Option<&WallType>, (With<Wall>, Without<Boundary>)>,
+) {
+    for (e, id, mut pos, mut player) in players.iter_mut() {
+        if !player.chaining {
+            continue;
+        }
+        player.chaining = false;
+        let held = keyboard_input.pressed(bindings.for_player(id).action)
+            || action_held(&gamepad_buttons, id);
+        if !chain_dig.0 || !held {
+            continue;
+        }
+        let cleared = arena.step(&pos, player.face_direction, &wrap);
+        // A larger player's own footprint doesn't get in its way
+        if entities_at(&grid, &cleared).iter().any(|o| *o != e) {
+            continue;
+        }
+        let next = arena.step(&cleared, player.face_direction, &wrap);
+        // Only carry on into walls that can actually be dug
+        if first_wall_at(&grid, &next).map_or(false, |e| is_diggable(&diggable, e)) {
+            *pos = cleared;
+            player.action = Action::Dig;
+        }
+    }
+}
+
+/// A diagonal step squeezing between two walls would clip through a corner, so
+/// it needs both orthogonal neighbours open as well.
+fn cuts_corner(grid: &WorldGrid, from: &Position, direction: Direction) -> bool {
+    let (dx, dy) = direction.offset();
+    dx != 0
+        && dy != 0
+        && (first_wall_at(
+            grid,
+            &Position {
+                x: from.x + dx,
+                ..*from
+            },
+        )
+        .is_some()
+            || first_wall_at(
+                grid,
+                &Position {
+                    y: from.y + dy,
+                    ..*from
+                },
+            )
+            .is_some())
+}
+
+fn validate_player_action(
+    practice: Res<PracticeMode>,
+    attack: Res<AttackByDig>,
+    capacity: Res<RockCapacity>,
+    arena: Res<ArenaConfig>,
+    wrap: Res<WrapMode>,
+    grid: Res<WorldGrid>,
+    boulders: Query<(), With<Boulder>>,
+    enemies: Query<(), With<Enemy>>,
+    mut players: Query<(&Position, &mut Player, &Footprint)>,
+) {
+    let boulder_at = |cell: &Position| {
+        entities_at(&grid, cell)
+            .into_iter()
+            .find(|e| boulders.get(*e).is_ok())
+    };
+    let wall_at = |cell: &Position| first_wall_at(&grid, cell).is_some();
+    let enemy_at = |cell: &Position| {
+        entities_at(&grid, cell)
+            .iter()
+            .any(|e| enemies.get(*e).is_ok())
+    };
+    // Cells players stand on or are moving into this tick, so two can't end up together
+    let mut claimed: Vec<Position> = players
+        .iter_mut()
+        .flat_map(|(p, _, footprint)| footprint.cells(p))
+        .collect();
+    for (pos, mut player, footprint) in players.iter_mut() {
+        player.pushing = None;
+        let ahead = footprint.ahead(pos, player.face_direction, &arena, &wrap);
+        let target_position = ahead[0];
+        let single = footprint.0.len() == 1;
+        let facing_enemy = attack.0 && ahead.iter().any(enemy_at);
+        // The action key is contextual: attack a faced enemy, dig into walls while
+        // there's room for the rock, build into open cells while carrying one
+        if player.action == Action::Dig || player.action == Action::Build {
+            let facing_wall = ahead.iter().any(wall_at);
+            let can_dig = practice.0 || player.rocks < capacity.0;
+            let can_build = practice.0 || player.rocks > 0;
+            player.action = match (facing_enemy, facing_wall, can_dig, can_build) {
+                (true, _, _, _) | (false, true, true, _) => Action::Dig,
+                (false, false, _, true) => Action::Build,
+                _ => Action::Idle,
+            };
+        }
+        match player.action {
+            Action::Move => {
+                // Don't rely on boundary walls alone, they can go missing
+                let blocked = ahead
+                    .iter()
+                    .any(|cell| wall_at(cell) || !arena.contains(cell) || claimed.contains(cell));
+                let cuts = footprint
+                    .cells(pos)
+                    .iter()
+                    .any(|cell| cuts_corner(&grid, cell, player.face_direction));
+                let boulder = ahead.iter().find_map(boulder_at);
+                if blocked || cuts || (!single && boulder.is_some()) {
+                    // Only single-cell players push boulders
+                    player.action = Action::Idle;
+                } else if let Some(boulder) = boulder {
+                    // Boulders roll straight along into open ground; only an
+                    // enemy, which gets crushed, may be in the way
+                    let (dx, dy) = player.face_direction.offset();
+                    let beyond = arena.step(&target_position, player.face_direction, &wrap);
+                    let clear = (dx == 0 || dy == 0)
+                        && arena.contains(&beyond)
+                        && !claimed.contains(&beyond)
+                        && entities_at(&grid, &beyond)
+                            .iter()
+                            .all(|e| enemies.get(*e).is_ok());
+                    if clear {
+                        player.pushing = Some(boulder);
+                        claimed.push(beyond);
+                    } else {
+                        player.action = Action::Idle;
+                    }
+                }
+            }
+            Action::Build => {
+                if first_wall_at(&grid, &target_position).is_some()
+                    || boulder_at(&target_position).is_some()
+                {
+                    player.action = Action::Idle;
+                }
+            }
+            Action::Dig => {
+                if !facing_enemy && !ahead.iter().any(wall_at) {
+                    player.action = Action::Idle;
+                }
+            }
+            Action::Idle => {}
+        }
+        // A sprint covers a second cell only if that one is open too
+        if player.action == Action::Move {
+            let second = arena.step(&target_position, player.face_direction, &wrap);
+            player.stride = if player.sprinting
+                && single
+                && player.pushing.is_none()
+                && first_wall_at(&grid, &second).is_none()
+                && boulder_at(&second).is_none()
+                && !cuts_corner(&grid, &target_position, player.face_direction)
+                && arena.contains(&second)
+                && !claimed.contains(&second)
+            {
+                claimed.push(second);
+                2
+            } else {
+                claimed.extend(ahead);
+                1
+            };
+        }
+    }
+}
+
+/// Set when the game ended because a player was walled in, so the game over
+/// screen can say so.
+#[derive(Default)]
+struct Trapped(bool);
+
+/// Seconds between checks for players who have walled themselves in.
+const TRAPPED_CHECK_SECONDS: f32 = 1.0;
+
+/// Ends the game when a player is walled in on all four sides and can't dig
+/// out, either because every wall around them is undiggable or because they
+/// have no room left for another rock. Spawn-time reachability can't catch a
+/// player who digs and builds their way into a pocket.
+fn check_player_trapped(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    practice: Res<PracticeMode>,
+    capacity: Res<RockCapacity>,
+    arena: Res<ArenaConfig>,
+    wrap: Res<WrapMode>,
+    grid: Res<WorldGrid>,
+    mut since_check: Local<f32>,
+    mut trapped: ResMut<Trapped>,
+    mut state: ResMut<State<GameState>>,
+    diggable: Query<Option<&WallType>, (With<Wall>, Without<Boundary>)>,
+    players: Query<(&Position, &Player)>,
+) {
+    if paused.0 {
+        return;
+    }
+    *since_check += scale.frame(&time).as_secs_f32();
+    if *since_check < TRAPPED_CHECK_SECONDS {
+        return;
+    }
+    *since_check = 0.;
+    // Diagonal steps cut a corner whenever both sides are walls, so the four
+    // cardinal neighbours decide whether a player can move at all
+    let sides = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+    let walled_in = players.iter().any(|(pos, player)| {
+        let mut walls = sides.iter().map(|d| {
+            let side = arena.step(pos, *d, &wrap);
+            (arena.contains(&side), first_wall_at(&grid, &side))
+        });
+        let can_dig = practice.0 || player.rocks < capacity.0;
+        walls.all(|(inside, wall)| {
+            !inside || matches!(wall, Some(e) if !(can_dig && is_diggable(&diggable, e)))
+        })
+    });
+    if walled_in {
+        trapped.0 = true;
+        state.overwrite_set(GameState::GameOver).unwrap();
+    }
+}
+
+/// Seconds between checks for a cleared board.
+const CLEAR_CHECK_SECONDS: f32 = 1.0;
+
+/// Wins the game once no diggable interior walls remain; bedrock can't be
+/// cleared, so it doesn't count. An empty board only counts after some digging,
+/// since random walls trickle in from nothing at the start.
+fn check_clear(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    score: Res<Score>,
+    mut since_check: Local<f32>,
+    mut state: ResMut<State<GameState>>,
+    interior_walls: Query<Option<&WallType>, (With<Wall>, Without<Boundary>)>,
+) {
+    if paused.0 {
+        return;
+    }
+    *since_check += scale.frame(&time).as_secs_f32();
+    if *since_check < CLEAR_CHECK_SECONDS {
+        return;
+    }
+    *since_check = 0.;
+    let cleared = interior_walls
+        .iter()
+        .all(|kind| kind == Some(&WallType::Bedrock));
+    if score.dug > 0 && cleared {
+        state.overwrite_set(GameState::Win).unwrap();
+    }
+}
+
+fn spawn_win(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    materials: Res<Materials>,
+    localization: Res<Localization>,
+    play_time: Res<PlayTime>,
+) {
+    let summary = format!(
+        "{}\n{}: {:.1}s\n{}",
+        localization.t("win.title"),
+        localization.t("hud.time"),
+        play_time.0,
+        localization.t("game_over.restart")
+    );
+    let screen = spawn_centered_text(&mut commands, &fonts, &materials, &summary);
+    commands.entity(screen).insert(WinUi);
+}
+
+fn despawn_win(mut commands: Commands, screens: Query<Entity, With<WinUi>>) {
+    for e in screens.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+/// Fading footprint left on a vacated cell. It has no `Position`, so the grid
+/// never sees it and it can't block anything.
+struct TrailFade;
+
+const TRAIL_SECONDS: f32 = 0.5;
+const TRAIL_ALPHA: f32 = 0.4;
+
+/// Drops a footprint where the player is drawn now, in a material of its own so
+/// it can fade independently.
+fn spawn_trail(
+    commands: &mut Commands,
+    materials: &mut Assets<ColorMaterial>,
+    transform: &Transform,
+    size: Vec2,
+    color: Color,
+) {
+    let mut color = color;
+    color.set_a(TRAIL_ALPHA);
+    let mut faded = color;
+    faded.set_a(0.);
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.add(color.into()),
+            sprite: Sprite::new(size),
+            transform: Transform::from_xyz(transform.translation.x, transform.translation.y, -0.01),
+            ..Default::default()
+        })
+        .insert(TrailFade)
+        .insert(ColorTween(Tween::new(
+            color,
+            faded,
+            TRAIL_SECONDS,
+            Easing::Linear,
+        )))
+        .insert(TweenDespawn);
+}
+
+const SPAWN_FADE_SECONDS: f32 = 0.3;
+
+/// Fades a new wall in from transparent. Meanwhile the wall draws with a
+/// material of its own under a `ColorTween`, going back to the shared one once
+/// the tween is done.
+struct SpawnFade {
+    /// The wall's usual material, taken when the fade starts.
+    shared: Option<Handle<ColorMaterial>>,
+}
+
+impl SpawnFade {
+    fn new() -> Self {
+        Self { shared: None }
+    }
+}
+
+/// Runs in `PostUpdate` so a wall spawned this frame is already see-through
+/// when first drawn.
+fn fade_in_walls(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut walls: Query<(
+        Entity,
+        &mut SpawnFade,
+        &mut Handle<ColorMaterial>,
+        Option<&ColorTween>,
+    )>,
+) {
+    for (e, mut fade, mut handle, tween) in walls.iter_mut() {
+        match (&fade.shared, tween) {
+            (None, _) => {
+                let color = materials.get(&*handle).map_or(Color::WHITE, |m| m.color);
+                let mut clear = color;
+                clear.set_a(0.);
+                fade.shared = Some(std::mem::replace(&mut *handle, materials.add(clear.into())));
+                commands.entity(e).insert(ColorTween(Tween::new(
+                    clear,
+                    color,
+                    SPAWN_FADE_SECONDS,
+                    Easing::EaseOut,
+                )));
+            }
+            (Some(_), None) => {
+                let own = std::mem::replace(&mut *handle, fade.shared.take().unwrap());
+                materials.remove(own);
+                commands.entity(e).remove::<SpawnFade>();
+            }
+            (Some(_), Some(_)) => {}
+        }
+    }
+}
+
+/// A flying chip of a destroyed wall.
+struct Debris;
+
+const DEBRIS_COUNT: usize = 8;
+const DEBRIS_SECONDS: f32 = 0.4;
+
+/// Bursts a broken wall into chips of its colour. One material per burst is
+/// shared by its chips, each running the same fade, so they fade together.
+/// The scatter is only for show, so it stays off `GameRng`.
+fn spawn_debris(
+    commands: &mut Commands,
+    materials: &mut Assets<ColorMaterial>,
+    transform: &Transform,
+    sprite: &Sprite,
+    color: Color,
+) {
+    let material = materials.add(color.into());
+    let mut faded = color;
+    faded.set_a(0.);
+    // Sizes and speeds follow the wall's drawn size so bursts scale with the window
+    let cell = sprite.size.x;
+    for _ in 0..DEBRIS_COUNT {
+        let angle = rand::random::<f32>() * std::f32::consts::TAU;
+        let speed = cell * (2. + 4. * rand::random::<f32>());
+        let start = Vec3::new(transform.translation.x, transform.translation.y, 0.2);
+        let velocity = Vec2::new(angle.cos(), angle.sin()) * speed;
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: material.clone(),
+                sprite: Sprite::new(Vec2::splat(cell * 0.15)),
+                transform: Transform::from_translation(start),
+                ..Default::default()
+            })
+            .insert(Debris)
+            .insert(TranslationTween(Tween::new(
+                start,
+                start + (velocity * DEBRIS_SECONDS).extend(0.),
+                DEBRIS_SECONDS,
+                Easing::Linear,
+            )))
+            .insert(ColorTween(Tween::new(
+                color,
+                faded,
+                DEBRIS_SECONDS,
+                Easing::Linear,
+            )))
+            .insert(TweenDespawn);
+    }
+}
+
+const POPUP_SECONDS: f32 = 0.8;
+
+/// Floats the points a treasure paid out up from where its wall stood.
+fn spawn_score_popup(
+    commands: &mut Commands,
+    fonts: &Fonts,
+    transform: &Transform,
+    sprite: &Sprite,
+    points: u32,
+) {
+    let start = Vec3::new(transform.translation.x, transform.translation.y, 0.3);
+    let rise = Vec3::new(0., sprite.size.y * 1.5, 0.);
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section(
+                format!("+{}", points),
+                TextStyle {
+                    font: fonts.ui.clone(),
+                    font_size: 16.0,
+                    color: TREASURE_COLOR,
+                },
+                TextAlignment {
+                    vertical: VerticalAlign::Center,
+                    horizontal: HorizontalAlign::Center,
+                },
+            ),
+            transform: Transform::from_translation(start),
+            ..Default::default()
+        })
+        .insert(TranslationTween(Tween::new(
+            start,
+            start + rise,
+            POPUP_SECONDS,
+            Easing::EaseOut,
+        )))
+        .insert(TweenDespawn);
+}
+
+fn player_move_action(
+    mut commands: Commands,
+    style: Res<MovementStyle>,
+    timesteps: Res<Timesteps>,
+    arena: Res<ArenaConfig>,
+    wrap: Res<WrapMode>,
+    grid: Res<WorldGrid>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut player_positions: Query<(
+        Entity,
+        &mut Position,
+        &mut Player,
+        &Transform,
+        &TextureAtlasSprite,
+    )>,
+    mut boulders: Query<&mut Position, (With<Boulder>, Without<Player>)>,
+    enemies: Query<(), With<Enemy>>,
 ) {
-    for (pos, mut player) in players.iter_mut() {
-        match player.action {
-            Action::Move => match player.face_direction {
-                Direction::Down => {
-                    let target_position = Position {
-                        x: pos.x,
-                        y: pos.y - 1,
-                    };
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Idle;
-                        }
-                    }
-                }
-                Direction::Up => {
-                    let target_position = Position {
-                        x: pos.x,
-                        y: pos.y + 1,
-                    };
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Idle;
+    for (e, mut pos, mut player, transform, sprite) in player_positions.iter_mut() {
+        if player.action == Action::Move {
+            let size = transform.scale.truncate() * PLAYER_FRAME_SIZE;
+            spawn_trail(&mut commands, &mut materials, transform, size, sprite.color);
+            if let Some(boulder) = player.pushing.take() {
+                if let Ok(mut boulder_pos) = boulders.get_mut(boulder) {
+                    let from = *boulder_pos;
+                    *boulder_pos = arena.step(&boulder_pos, player.face_direction, &wrap);
+                    for e in entities_at(&grid, &boulder_pos) {
+                        if enemies.get(e).is_ok() {
+                            commands.entity(e).despawn();
                         }
                     }
-                }
-                Direction::Left => {
-                    let target_position = Position {
-                        x: pos.x - 1,
-                        y: pos.y,
-                    };
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Idle;
-                        }
+                    if (boulder_pos.x - from.x).abs() + (boulder_pos.y - from.y).abs() == 1 {
+                        commands.entity(boulder).insert(MoveAnimation::new(
+                            from,
+                            *boulder_pos,
+                            timesteps.action_step() as f32,
+                        ));
                     }
-                }
-                Direction::Right => {
-                    let target_position = Position {
-                        x: pos.x + 1,
-                        y: pos.y,
-                    };
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Idle;
-                        }
+                    if player.sprinting {
+                        commands.entity(boulder).insert(Sliding {
+                            direction: player.face_direction,
+                        });
                     }
                 }
-            },
-            Action::Dig => match player.face_direction {
-                Direction::Down => {
-                    let target_position = Position {
-                        x: pos.x,
-                        y: pos.y - 1,
-                    };
-                    player.action = Action::Idle;
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Dig;
-                            break;
-                        }
+            }
+            let from = *pos;
+            let mut wrapped = false;
+            for _ in 0..player.stride {
+                let next = arena.step(&pos, player.face_direction, &wrap);
+                wrapped |= next != pos.translated(player.face_direction);
+                *pos = next;
+            }
+            // Sliding across the whole arena would look wrong, so a wrap just jumps
+            if !wrapped {
+                commands.entity(e).insert(MoveAnimation::new(
+                    from,
+                    *pos,
+                    timesteps.action_step() as f32,
+                ));
+            }
+            if *style == MovementStyle::StepStop {
+                player.action = Action::Idle;
+            }
+        }
+    }
+}
+
+/// Lands one dig on a wall. Tough walls soak up a dig each until they break;
+/// returns whether this one broke it. `crumbling` holds the walls already
+/// shrinking away when `AnimatedDig` is on; a break then starts the wall
+/// crumbling instead of removing it, and a crumbling wall takes no more digs.
+fn dig_wall(
+    commands: &mut Commands,
+    toughness: &mut Query<&mut Toughness>,
+    crumbling: Option<&Query<(), With<Digging>>>,
+    e: Entity,
+) -> bool {
+    if crumbling.map_or(false, |crumbling| crumbling.get(e).is_ok()) {
+        return false;
+    }
+    if let Ok(mut toughness) = toughness.get_mut(e) {
+        toughness.hits_remaining = toughness.hits_remaining.saturating_sub(1);
+        if toughness.hits_remaining > 0 {
+            return false;
+        }
+    }
+    match crumbling {
+        // Scaling the transform shrinks any crack along with the wall
+        Some(_) => {
+            commands
+                .entity(e)
+                .insert(Digging)
+                .insert(ScaleTween(Tween::new(
+                    Vec3::ONE,
+                    Vec3::ZERO,
+                    DIG_SECONDS,
+                    Easing::EaseInOut,
+                )))
+                .insert(TweenDespawn);
+        }
+        // Takes any crack overlay with it
+        None => commands.entity(e).despawn_recursive(),
+    }
+    true
+}
+
+/// Shrink a broken wall away instead of removing it at once. The cell stays
+/// blocked until the wall is gone.
+#[derive(Default, Deserialize)]
+pub struct AnimatedDig(pub bool);
+
+/// Digging while facing an enemy hits it instead, on the dig cooldown, and an
+/// enemy out of hit points is gone. Should a wall share the cell, the enemy
+/// takes the hit, being the one that can catch the player.
+#[derive(Default, Deserialize)]
+pub struct AttackByDig(pub bool);
+
+/// Seconds a broken wall takes to crumble away under `AnimatedDig`.
+const DIG_SECONDS: f32 = 0.2;
+
+/// A broken wall crumbling away.
+struct Digging;
+
+/// Crack drawn over a wall that has taken digs but not broken yet.
+struct WallCrack;
+
+/// Grows a wall's crack with the share of its hits already taken.
+fn render_wall_damage(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    walls: Query<(Entity, &WallType, &Toughness, Option<&Children>), Changed<Toughness>>,
+    mut cracks: Query<&mut Size, With<WallCrack>>,
+) {
+    for (e, kind, toughness, children) in walls.iter() {
+        let max = kind.hits();
+        if toughness.hits_remaining == 0 || toughness.hits_remaining >= max {
+            continue;
+        }
+        let damage = 1. - toughness.hits_remaining as f32 / max as f32;
+        let size = Size::square(0.8 * damage);
+        let crack = children
+            .into_iter()
+            .flat_map(|children| children.iter())
+            .find(|child| cracks.get_mut(**child).is_ok());
+        match crack {
+            Some(crack) => *cracks.get_mut(*crack).unwrap() = size,
+            None => {
+                commands.entity(e).with_children(|parent| {
+                    parent
+                        .spawn_bundle(SpriteBundle {
+                            material: materials.crack_material.clone(),
+                            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                            transform: Transform::from_xyz(0., 0., 0.1),
+                            ..Default::default()
+                        })
+                        .insert(WallCrack)
+                        .insert(size);
+                });
+            }
+        }
+    }
+}
+
+fn player_dig_action(
+    mut commands: Commands,
+    // Paired up to stay within Bevy's limit on system parameters
+    (mut score, mut combo, mut achievements): (ResMut<Score>, ResMut<Combo>, ResMut<Achievements>),
+    (chain_dig, practice): (Res<ChainDig>, Res<PracticeMode>),
+    (capacity, limit, mut budget): (
+        Res<RockCapacity>,
+        Res<BuildLimit>,
+        Option<ResMut<BuildBudget>>,
+    ),
+    (animated, crumbling): (Res<AnimatedDig>, Query<(), With<Digging>>),
+    (attack, mut enemies): (Res<AttackByDig>, Query<&mut EnemyHealth, With<Enemy>>),
+    (audio, game_audio): (Res<Audio>, Res<GameAudio>),
+    (arena, wrap): (Res<ArenaConfig>, Res<WrapMode>),
+    (mut shake, fonts): (ResMut<CameraShake>, Res<Fonts>),
+    grid: Res<WorldGrid>,
+    mut history: ResMut<ActionHistory>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut players: Query<(
+        &PlayerId,
+        &Position,
+        &mut Player,
+        &Footprint,
+        &mut DigCooldown,
+    )>,
+    diggable: Query<Option<&WallType>, (With<Wall>, Without<Boundary>)>,
+    walls: Query<(&Transform, &Sprite, &Handle<ColorMaterial>), With<Wall>>,
+    (mut toughness, treasures): (Query<&mut Toughness>, Query<&Treasure>),
+) {
+    for (id, position, mut player, footprint, mut cooldown) in players.iter_mut() {
+        if player.action == Action::Dig {
+            if !cooldown.0.finished() {
+                player.tunneling = false;
+                player.action = Action::Idle;
+                continue;
+            }
+            let ahead = footprint.ahead(position, player.face_direction, &arena, &wrap);
+            let target = ahead.iter().find_map(|cell| {
+                entities_at(&grid, cell)
+                    .into_iter()
+                    .find(|e| enemies.get_mut(*e).is_ok())
+            });
+            if let Some(enemy) = target.filter(|_| attack.0) {
+                if let Ok(mut health) = enemies.get_mut(enemy) {
+                    health.0 = health.0.saturating_sub(1);
+                    if health.0 == 0 {
+                        commands.entity(enemy).despawn();
                     }
                 }
-                Direction::Up => {
-                    let target_position = Position {
-                        x: pos.x,
-                        y: pos.y + 1,
-                    };
-                    player.action = Action::Idle;
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Dig;
-                            break;
-                        }
+                restart_cooldown(&mut cooldown.0);
+                audio.play(game_audio.dig.clone());
+                player.tunneling = false;
+                player.action = Action::Idle;
+                continue;
+            }
+            let mut dug = 0;
+            // Larger players dig out their whole leading edge, but don't tunnel
+            let tunneling = player.tunneling && ahead.len() == 1;
+            for mut pos in ahead {
+                // A tunnel keeps going until open ground, the boundary or a full load
+                let crumbling = Some(&crumbling).filter(|_| animated.0);
+                while let Some(e) = first_wall_at(&grid, &pos).filter(|e| {
+                    is_diggable(&diggable, *e) && crumbling.map_or(true, |c| c.get(*e).is_err())
+                }) {
+                    if !dig_wall(&mut commands, &mut toughness, crumbling, e) {
+                        dug += 1;
+                        break;
                     }
-                }
-                Direction::Left => {
-                    let target_position = Position {
-                        x: pos.x - 1,
-                        y: pos.y,
-                    };
-                    player.action = Action::Idle;
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Dig;
-                            break;
-                        }
+                    shake.start();
+                    if let Ok((transform, sprite, material)) = walls.get(e) {
+                        let color = materials.get(material).map_or(Color::WHITE, |m| m.color);
+                        spawn_debris(&mut commands, &mut materials, transform, sprite, color);
                     }
-                }
-                Direction::Right => {
-                    let target_position = Position {
-                        x: pos.x + 1,
-                        y: pos.y,
-                    };
-                    player.action = Action::Idle;
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Dig;
-                            break;
-                        }
+                    if practice.0 {
+                        history.push(ActionRecord {
+                            kind: Action::Dig,
+                            position: pos,
+                            player: *id,
+                            wall: diggable.get(e).ok().flatten().copied(),
+                            rocks: player.rocks,
+                        });
                     }
-                }
-            },
-            Action::Build => match player.face_direction {
-                Direction::Down => {
-                    let target_position = Position {
-                        x: pos.x,
-                        y: pos.y - 1,
-                    };
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Idle;
+                    score.record_dig(*id, combo.extend());
+                    if let Ok(Treasure(bonus)) = treasures.get(e) {
+                        score.record_bonus(*id, *bonus);
+                        if let Ok((transform, sprite, _)) = walls.get(e) {
+                            spawn_debris(
+                                &mut commands,
+                                &mut materials,
+                                transform,
+                                sprite,
+                                TREASURE_COLOR,
+                            );
+                            spawn_score_popup(&mut commands, &fonts, transform, sprite, *bonus);
                         }
                     }
-                }
-                Direction::Up => {
-                    let target_position = Position {
-                        x: pos.x,
-                        y: pos.y + 1,
-                    };
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Idle;
-                        }
+                    if !practice.0 {
+                        achievements.walls_dug += 1;
                     }
-                }
-                Direction::Left => {
-                    let target_position = Position {
-                        x: pos.x - 1,
-                        y: pos.y,
-                    };
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Idle;
-                        }
+                    if let Some(budget) = budget.as_mut().filter(|_| limit.dig_refunds) {
+                        budget.0 += 1;
                     }
-                }
-                Direction::Right => {
-                    let target_position = Position {
-                        x: pos.x + 1,
-                        y: pos.y,
-                    };
-                    for w in walls.iter() {
-                        if &target_position == w {
-                            player.action = Action::Idle;
-                        }
+                    player.rocks = (player.rocks + 1).min(capacity.0);
+                    dug += 1;
+                    if !tunneling || (!practice.0 && player.rocks >= capacity.0) {
+                        break;
                     }
+                    pos = arena.step(&pos, player.face_direction, &wrap);
                 }
-            },
-            Action::Idle => {}
+            }
+            if dug > 0 {
+                restart_cooldown(&mut cooldown.0);
+                audio.play(game_audio.dig.clone());
+                player.chaining = chain_dig.0 && !player.tunneling;
+            }
+            player.tunneling = false;
+            player.action = Action::Idle;
         }
     }
 }
 
-fn player_move_action(mut player_positions: Query<(&mut Position, &mut Player)>) {
-    for (mut pos, mut player) in player_positions.iter_mut() {
-        if player.action == Action::Move {
-            match player.face_direction {
-                Direction::Down => {
-                    pos.y -= 1;
-                }
-                Direction::Up => {
-                    pos.y += 1;
-                }
-                Direction::Left => {
-                    pos.x -= 1;
-                }
-                Direction::Right => {
-                    pos.x += 1;
-                }
-            }
-            player.action = Action::Idle;
-        }
+fn practice_reset(
+    mut commands: Commands,
+    practice: Res<PracticeMode>,
+    keyboard_input: Res<Input<KeyCode>>,
+    walls: Query<Entity, (With<Wall>, Without<Boundary>)>,
+    arena: Res<ArenaConfig>,
+    spawn: Res<PlayerSpawn>,
+    level: Option<Res<Level>>,
+    mut history: ResMut<ActionHistory>,
+    mut players: Query<(&PlayerId, &mut Position, &mut Player)>,
+) {
+    if !practice.0 || !keyboard_input.just_pressed(KeyCode::Back) {
+        return;
     }
+    for e in walls.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    for (id, mut pos, mut player) in players.iter_mut() {
+        *pos = id.start(&arena, &spawn, level.as_deref());
+        player.action = Action::Idle;
+    }
+    history.0.clear();
 }
 
-fn player_dig_action(
+/// Takes back the newest practice dig or build. A dug cell someone has since
+/// stepped into can't be refilled, so the record waits until it is clear.
+fn practice_undo(
     mut commands: Commands,
-    mut players: Query<(&Position, &mut Player)>,
-    mut walls: Query<(Entity, &Position, (With<Wall>, Without<Boundary>))>,
+    practice: Res<PracticeMode>,
+    keyboard_input: Res<Input<KeyCode>>,
+    materials: Res<Materials>,
+    grid: Res<WorldGrid>,
+    mut history: ResMut<ActionHistory>,
+    mut players: Query<(&PlayerId, &mut Player)>,
 ) {
-    for (position, mut player) in players.iter_mut() {
-        let mut pos = *position;
-        if player.action == Action::Dig {
-            match player.face_direction {
-                Direction::Down => {
-                    pos.y -= 1;
-                }
-                Direction::Up => {
-                    pos.y += 1;
-                }
-                Direction::Left => {
-                    pos.x -= 1;
-                }
-                Direction::Right => {
-                    pos.x += 1;
-                }
+    if !practice.0 || !keyboard_input.just_pressed(KeyCode::Z) {
+        return;
+    }
+    let record = match history.0.back() {
+        Some(record) => record,
+        None => return,
+    };
+    match record.kind {
+        Action::Dig => {
+            if !entities_at(&grid, &record.position).is_empty() {
+                return;
             }
-            for (e, wpos, _w) in walls.iter_mut() {
-                if wpos == &pos {
-                    commands.entity(e).despawn();
-                    player.has_rock = true;
-                }
+            let mut wall = commands.spawn_bundle(SpriteBundle {
+                material: record.wall.map_or(materials.wall_material.clone(), |kind| {
+                    materials.wall_for(kind)
+                }),
+                sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                ..Default::default()
+            });
+            wall.insert(Wall)
+                .insert(record.position)
+                .insert(Size::square(0.8));
+            if let Some(kind) = record.wall {
+                wall.insert(kind).insert(Toughness {
+                    hits_remaining: kind.hits(),
+                });
             }
-            player.action = Action::Idle;
+        }
+        // Only digs and builds are recorded
+        _ => {
+            if let Some(e) = first_wall_at(&grid, &record.position) {
+                commands.entity(e).despawn();
+            }
+        }
+    }
+    for (id, mut player) in players.iter_mut() {
+        if *id == record.player {
+            player.rocks = record.rocks;
         }
     }
+    history.0.pop_back();
 }
 
 fn player_build_action(
     mut commands: Commands,
+    mut score: ResMut<Score>,
+    mut achievements: ResMut<Achievements>,
+    practice: Res<PracticeMode>,
     materials: Res<Materials>,
-    mut players: Query<(&Position, &mut Player)>,
+    audio: Res<Audio>,
+    game_audio: Res<GameAudio>,
+    arena: Res<ArenaConfig>,
+    wrap: Res<WrapMode>,
+    grid: Res<WorldGrid>,
+    mut history: ResMut<ActionHistory>,
+    mut budget: Option<ResMut<BuildBudget>>,
+    mut players: Query<(
+        &PlayerId,
+        &Position,
+        &mut Player,
+        &Footprint,
+        &mut BuildCooldown,
+    )>,
 ) {
-    for (position, mut player) in players.iter_mut() {
-        let mut pos = *position;
+    // Players may have moved this tick, so check their live positions as well as the grid
+    let mut occupied: Vec<Position> = players
+        .iter_mut()
+        .flat_map(|(_, p, _, footprint, _)| footprint.cells(p))
+        .collect();
+    for (id, position, mut player, footprint, mut cooldown) in players.iter_mut() {
         if player.action == Action::Build {
-            match player.face_direction {
-                Direction::Down => {
-                    pos.y -= 1;
-                }
-                Direction::Up => {
-                    pos.y += 1;
-                }
-                Direction::Left => {
-                    pos.x -= 1;
-                }
-                Direction::Right => {
-                    pos.x += 1;
-                }
+            let pos = footprint.ahead(position, player.face_direction, &arena, &wrap)[0];
+            let spent = !practice.0 && budget.as_ref().map_or(false, |budget| budget.0 == 0);
+            let cooling = !cooldown.0.finished();
+            if first_wall_at(&grid, &pos).is_some() || occupied.contains(&pos) || spent || cooling {
+                // Keep the rock for another try
+                player.action = Action::Idle;
+                continue;
             }
+            occupied.push(pos);
             commands
                 .spawn_bundle(SpriteBundle {
                     material: materials.wall_material.clone(),
@@ -496,40 +3726,816 @@ fn player_build_action(
                 })
                 .insert(Wall)
                 .insert(pos)
-                .insert(Size::square(0.8));
-            player.has_rock = false;
+                .insert(Size::square(0.8))
+                .insert(SpawnFade::new());
+            restart_cooldown(&mut cooldown.0);
+            audio.play(game_audio.build.clone());
+            score.record_build(*id);
+            if !practice.0 {
+                achievements.walls_built += 1;
+            }
+            if practice.0 {
+                history.push(ActionRecord {
+                    kind: Action::Build,
+                    position: pos,
+                    player: *id,
+                    wall: None,
+                    rocks: player.rocks,
+                });
+            } else {
+                player.rocks = player.rocks.saturating_sub(1);
+                if let Some(budget) = budget.as_mut() {
+                    budget.0 -= 1;
+                }
+            }
             player.action = Action::Idle;
         }
     }
 }
 
 fn main() {
-    App::build()
-        .insert_resource(WindowDescriptor {
-            title: "Carnival".to_string(),
-            width: 400.0,
-            height: 400.0,
-            ..Default::default()
-        })
-        .insert_resource(ClearColor(Color::rgb(0.04, 0.04, 0.04)))
+    let language =
+        env::var("CARNIVAL_LANG").unwrap_or_else(|_| localization::DEFAULT_LANGUAGE.to_string());
+    let localization = Localization::load(&language);
+    let config = GameConfig::load();
+    let level = env::var("CARNIVAL_LEVEL")
+        .ok()
+        .and_then(|name| load_level(&format!("{}/{}.txt", LEVEL_DIR, name)));
+    let seed = env::var("CARNIVAL_SEED")
+        .ok()
+        .and_then(|seed| match seed.parse() {
+            Ok(seed) => Some(seed),
+            Err(e) => {
+                warn!("Ignoring CARNIVAL_SEED {:?}: {}", seed, e);
+                None
+            }
+        });
+    if let Some(ticks) = env::var("CARNIVAL_SIMULATE")
+        .ok()
+        .and_then(|ticks| ticks.parse().ok())
+    {
+        let config = SimConfig {
+            game: config,
+            level,
+            seed: seed.unwrap_or_default(),
+        };
+        println!("{}", simulate_headless(config, &[], ticks));
+        return;
+    }
+    let mut app = App::build();
+    app.insert_resource(WindowDescriptor {
+        title: localization.t("window.title").to_string(),
+        width: 400.0,
+        height: 400.0,
+        ..Default::default()
+    });
+    add_game(&mut app, config, level, localization, seed, GameState::Menu);
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .run();
+}
+
+/// Everything but Bevy's own plugins, which the caller adds: `main` the
+/// defaults, with a window, and `simulate_headless` just enough to run without
+/// one.
+fn add_game(
+    app: &mut AppBuilder,
+    config: GameConfig,
+    level: Option<Level>,
+    localization: Localization,
+    seed: Option<u64>,
+    initial_state: GameState,
+) {
+    let arena = match &level {
+        Some(level) => ArenaConfig {
+            width: level.width,
+            height: level.height,
+        },
+        None => config.arena,
+    };
+    let spawn = level
+        .as_ref()
+        .and_then(|level| level.start(0))
+        .map_or(config.player_spawn, PlayerSpawn)
+        .validated(&arena, level.as_ref());
+    let mut rng = GameRng::new(seed);
+    let game_seed = GameSeed::roll(&mut rng);
+    if let Some(level) = level {
+        app.insert_resource(level);
+    }
+    app.insert_resource(ClearColor(Color::rgb(0.04, 0.04, 0.04)))
+        .insert_resource(arena)
+        .insert_resource(localization)
+        .insert_resource(config.parallax)
+        .insert_resource(config.chain_dig)
+        .insert_resource(config.auto_play_assist)
+        .insert_resource(config.practice)
+        .insert_resource(config.wrap)
+        .insert_resource(config.animated_dig)
+        .insert_resource(config.attack_by_dig)
+        .insert_resource(config.timed)
+        .insert_resource(config.movement_style)
+        .insert_resource(config.scaling)
+        .init_resource::<CameraFollow>()
+        .init_resource::<CameraMode>()
+        .init_resource::<CameraShake>()
+        .init_resource::<Score>()
+        .insert_resource(config.player_count)
+        .insert_resource(config.player_size)
+        .insert_resource(spawn)
+        .init_resource::<Lives>()
+        .init_resource::<Trapped>()
+        .init_resource::<PlayTime>()
+        .init_resource::<Combo>()
+        .init_resource::<SafeZone>()
+        .init_resource::<SpawnClaims>()
+        .init_resource::<ActionHistory>()
+        .insert_resource(config.rock_capacity)
+        .insert_resource(config.cooldowns)
+        .insert_resource(config.build_limit)
+        .insert_resource(DifficultyBase::new(&config.timesteps, &config.wall_density))
+        .init_resource::<Difficulty>()
+        .insert_resource(config.wall_density)
+        .insert_resource(config.treasure)
+        .init_resource::<DebugOverlay>()
+        .insert_resource(config.move_repeat)
+        .insert_resource(config.timesteps)
+        .init_resource::<ActionClock>()
+        .init_resource::<TimeScale>()
+        .insert_resource(KeyBindings::load())
+        .insert_resource(game_seed)
+        .insert_resource(rng)
+        .insert_resource(config.player_appearance)
+        .insert_resource(config.wall_appearance)
+        .insert_resource(config.fog)
+        .insert_resource(config.oxygen)
+        .insert_resource(config.water)
+        .insert_resource(config.fall_damage)
+        .insert_resource(config.trap_effect)
+        .init_resource::<Paused>()
         .add_startup_system(setup.system())
-        .add_startup_stage("player_loader", SystemStage::single(spawn_player.system()))
+        .add_startup_stage(
+            "background_loader",
+            SystemStage::single(spawn_parallax_background.system()),
+        )
         .add_system_set_to_stage(
             CoreStage::PostUpdate,
             SystemSet::new()
-                .with_system(position_translation.system())
-                .with_system(size_scaling.system()),
+                .with_system(position_translation.system().label("translation"))
+                .with_system(size_scaling.system())
+                .with_system(animate_player.system())
+                .with_system(update_face_highlight.system())
+                .with_system(update_build_preview.system())
+                .with_system(fade_in_walls.system())
+                .with_system(render_minimap.system())
+                .with_system(settle_camera_shake.system().before("camera"))
+                .with_system(free_camera.system().label("camera"))
+                .with_system(camera_follow.system().label("camera").after("translation"))
+                .with_system(apply_camera_shake.system().label("shake").after("camera"))
+                .with_system(parallax.system().after("shake")),
+        )
+        .add_system(localize_window_title.system())
+        .add_system(layout_hud.system())
+        .add_system(update_score_text.system())
+        .add_system(update_lives_text.system())
+        .add_system(update_timer_text.system())
+        .add_system(update_rocks_text.system())
+        .add_system(update_combo_text.system())
+        .add_system(update_budget_text.system())
+        .add_system(toggle_debug_overlay.system())
+        .add_system(cycle_camera_mode.system())
+        .add_system(toggle_slow_motion.system())
+        .add_system(diagnostics_text.system())
+        .add_system(animate_moves.system())
+        .add_system(render_wall_damage.system())
+        .add_startup_stage(
+            "highlight_loader",
+            SystemStage::single(spawn_face_highlights.system()),
         )
         .add_startup_stage(
-            "boundary_loader",
-            SystemStage::single(spawn_boundaries.system()),
+            "hud_loader",
+            SystemStage::parallel()
+                .with_system(spawn_score_text.system())
+                .with_system(spawn_lives_text.system())
+                .with_system(spawn_timer_text.system())
+                .with_system(spawn_rocks_text.system())
+                .with_system(spawn_combo_text.system())
+                .with_system(spawn_budget_text.system())
+                .with_system(spawn_diagnostics_text.system())
+                .with_system(spawn_minimap.system()),
+        )
+        .add_state(initial_state)
+        .add_system_set(
+            SystemSet::on_enter(GameState::Playing)
+                .with_system(apply_difficulty.system().label("difficulty"))
+                .with_system(spawn_boundaries.system().label("boundaries"))
+                .with_system(spawn_level.system().label("boundaries"))
+                .with_system(spawn_player.system().label("players").after("boundaries"))
+                .with_system(start_game_timer.system())
+                .with_system(reset_play_time.system())
+                .with_system(reset_safe_zone.system().before(WorldSteps::Walls))
+                .with_system(start_build_budget.system()),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(
+                    tick_game_timer
+                        .system()
+                        .label(WorldSteps::Timer)
+                        .after(WorldSteps::Clear),
+                )
+                .with_system(tick_play_time.system())
+                .with_system(tick_combo.system().after(PlayerActions::DigAction))
+                .with_system(
+                    tick_action_cooldowns
+                        .system()
+                        .before(PlayerActions::DigAction)
+                        .before(PlayerActions::BuildAction),
+                )
+                .with_system(
+                    check_player_trapped
+                        .system()
+                        .label(WorldSteps::Trapped)
+                        .after(WorldSteps::Oxygen),
+                )
+                .with_system(
+                    check_clear
+                        .system()
+                        .label(WorldSteps::Clear)
+                        .after(WorldSteps::Exit),
+                ),
+        )
+        .add_system_set(
+            SystemSet::on_enter(GameState::GameOver)
+                .with_system(record_high_score.system().label("high_score"))
+                .with_system(spawn_game_over.system().after("high_score")),
+        )
+        .add_system_set(SystemSet::on_update(GameState::GameOver).with_system(on_restart.system()))
+        .add_system_set(
+            SystemSet::on_exit(GameState::GameOver)
+                .with_system(despawn_game_over.system())
+                .with_system(despawn_arena.system()),
+        )
+        .add_system_set(
+            SystemSet::on_enter(GameState::Win)
+                .with_system(record_high_score.system())
+                .with_system(spawn_win.system()),
+        )
+        .add_system_set(SystemSet::on_update(GameState::Win).with_system(on_restart.system()))
+        .add_system_set(
+            SystemSet::on_exit(GameState::Win)
+                .with_system(despawn_win.system())
+                .with_system(despawn_arena.system()),
         )
         .add_system_set(
             SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(1.0))
-                .with_system(spawn_walls.system()),
+                .with_run_criteria(spawn_tick.system().chain(run_if_simulating.system()))
+                .with_system(
+                    spawn_walls
+                        .system()
+                        .label(WorldSteps::Walls)
+                        .after(PlayerActions::SimState),
+                ),
         )
         .add_plugin(PlayerActionPlugin)
-        .add_plugins(DefaultPlugins)
-        .run();
+        .add_plugin(EnemyPlugin)
+        .add_plugin(GemPlugin)
+        .add_plugin(BoulderPlugin)
+        .add_plugin(PortalPlugin)
+        .add_plugin(ExitPlugin)
+        .add_plugin(FogPlugin)
+        .add_plugin(CompassPlugin)
+        .add_plugin(OxygenPlugin)
+        .add_plugin(WaterPlugin)
+        .add_plugin(TrapPlugin)
+        .add_plugin(PalettePlugin)
+        .add_plugin(SavePlugin)
+        .add_plugin(SharePlugin)
+        .add_plugin(AchievementsPlugin)
+        .add_plugin(MenuPlugin)
+        .add_plugin(EditorPlugin)
+        .add_plugin(TweenPlugin);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enemy::EnemyKind;
+    use crate::gravity::FallDamageConfig;
+    use crate::Direction;
+    use bevy::ecs::schedule::SystemDescriptor;
+    use std::fs;
+
+    /// Runs one system over the world, applying its commands, then brings the
+    /// grid up to date with whatever it changed.
+    fn run(world: &mut World, system: impl Into<SystemDescriptor>) {
+        SystemStage::single(system).run(world);
+        SystemStage::single(update_world_grid.system()).run(world);
+        world.clear_trackers();
+    }
+
+    fn world_with_arena(width: u32, height: u32) -> World {
+        let mut world = World::default();
+        world.insert_resource(ArenaConfig { width, height });
+        world.insert_resource(WorldGrid::default());
+        world
+    }
+
+    /// Also holds the asset storage and materials the spawners draw from.
+    fn world_with_assets(width: u32, height: u32) -> World {
+        let mut app = App::build();
+        app.insert_resource(AssetServer::new(
+            bevy::asset::FileAssetIo::new("assets"),
+            bevy::tasks::TaskPool::new(),
+        ))
+        .add_plugin(bevy::asset::AssetPlugin)
+        .add_asset::<ColorMaterial>();
+        let mut world = std::mem::take(app.world_mut());
+        world.insert_resource(ArenaConfig { width, height });
+        world.insert_resource(WorldGrid::default());
+        world.insert_resource(Materials::default());
+        world
+    }
+
+    fn sync_grid(world: &mut World) {
+        SystemStage::single(update_world_grid.system()).run(world);
+        world.clear_trackers();
+    }
+
+    fn player(action: Action, face_direction: Direction) -> Player {
+        Player {
+            action,
+            face_direction,
+            rocks: 0,
+            chaining: false,
+            assisted: false,
+            sprinting: false,
+            tunneling: false,
+            throwing: false,
+            trapping: false,
+            stride: 1,
+            pushing: None,
+        }
+    }
+
+    #[test]
+    fn detect_overlaps_nudges_a_boulder_off_a_wall() {
+        let mut world = world_with_arena(6, 6);
+        let cell = Position { x: 2, y: 2 };
+        let wall = world.spawn().insert(cell).insert(Wall).id();
+        let boulder = world.spawn().insert(cell).insert(Boulder).id();
+        // An enemy on a gem is a pickup waiting to happen, not an overlap
+        let shared = Position { x: 4, y: 4 };
+        world.spawn().insert(shared).insert(Gem);
+        let enemy = world
+            .spawn()
+            .insert(shared)
+            .insert(Enemy {
+                kind: EnemyKind::Chaser,
+            })
+            .id();
+        sync_grid(&mut world);
+        assert_eq!(crowded_cells(world.get_resource().unwrap()).count(), 2);
+
+        run(&mut world, detect_overlaps.system());
+
+        let moved = *world.get::<Position>(boulder).unwrap();
+        assert_ne!(moved, cell);
+        assert!(world
+            .get_resource::<ArenaConfig>()
+            .unwrap()
+            .contains(&moved));
+        assert_eq!(*world.get::<Position>(wall).unwrap(), cell);
+        assert_eq!(*world.get::<Position>(enemy).unwrap(), shared);
+        let grid = world.get_resource::<WorldGrid>().unwrap();
+        assert_eq!(entities_at(grid, &cell).as_slice(), &[wall]);
+        assert_eq!(entities_at(grid, &moved).as_slice(), &[boulder]);
+    }
+
+    #[test]
+    fn detect_overlaps_removes_a_doubled_wall() {
+        let mut world = world_with_arena(6, 6);
+        let cell = Position { x: 3, y: 3 };
+        world.spawn().insert(cell).insert(Wall);
+        world.spawn().insert(cell).insert(Wall);
+        sync_grid(&mut world);
+
+        run(&mut world, detect_overlaps.system());
+
+        let grid = world.get_resource::<WorldGrid>().unwrap();
+        assert_eq!(entities_at(grid, &cell).len(), 1);
+        assert_eq!(crowded_cells(grid).count(), 0);
+    }
+
+    /// Where a sprinting player lands this tick, with walls at `walls`: the
+    /// resolved action and how many cells it covers.
+    fn sprint_towards(walls: &[Position]) -> (Action, u32) {
+        let mut world = world_with_arena(8, 8);
+        world.insert_resource(PracticeMode(false));
+        world.insert_resource(AttackByDig(false));
+        world.insert_resource(RockCapacity::default());
+        world.insert_resource(WrapMode(false));
+        for wall in walls {
+            world.spawn().insert(*wall).insert(Wall);
+        }
+        let mut sprinter = player(Action::Move, Direction::Right);
+        sprinter.sprinting = true;
+        let e = world
+            .spawn()
+            .insert(Position { x: 2, y: 2 })
+            .insert(sprinter)
+            .insert(Footprint::square(1))
+            .id();
+        sync_grid(&mut world);
+
+        run(&mut world, validate_player_action.system());
+
+        let player = world.get::<Player>(e).unwrap();
+        (player.action, player.stride)
+    }
+
+    #[test]
+    fn sprint_covers_two_open_cells() {
+        assert_eq!(sprint_towards(&[]), (Action::Move, 2));
+    }
+
+    #[test]
+    fn sprint_stops_short_of_a_wall_one_cell_beyond() {
+        assert_eq!(
+            sprint_towards(&[Position { x: 4, y: 2 }]),
+            (Action::Move, 1)
+        );
+    }
+
+    #[test]
+    fn sprint_into_an_adjacent_wall_goes_nowhere() {
+        assert_eq!(sprint_towards(&[Position { x: 3, y: 2 }]).0, Action::Idle);
+    }
+
+    #[test]
+    fn player_spawning_on_a_level_wall_moves_to_a_free_cell() {
+        let path = std::env::temp_dir().join("carnival-test-wall-at-start.txt");
+        fs::write(&path, "#####\n#...#\n#...#\n##..#\n#####\n").unwrap();
+        let level = load_level(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let blocked = Position { x: 1, y: 1 };
+        assert!(level.walls.contains(&blocked));
+
+        let mut world = world_with_assets(level.width, level.height);
+        world.insert_resource(PlayerSpawn::default());
+        world.insert_resource(PlayerCount(1));
+        world.insert_resource(MoveRepeat::default());
+        world.insert_resource(PlayerSize::default());
+        world.insert_resource(ActionCooldowns::default());
+        world.insert_resource(PlayerSprites::default());
+        world.insert_resource(SpawnClaims::default());
+        let walls = level.walls.clone();
+        world.insert_resource(level);
+
+        run(&mut world, spawn_player.system());
+
+        let starts: Vec<Position> = world
+            .query_filtered::<&Position, With<Player>>()
+            .iter(&world)
+            .copied()
+            .collect();
+        assert_eq!(starts.len(), 1);
+        let start = starts[0];
+        assert_ne!(start, blocked);
+        assert!(!walls.contains(&start));
+        assert!(start.x > 0 && start.y > 0 && start.x < 4 && start.y < 4);
+        // Nearest free cell to the requested spawn
+        assert_eq!((start.x - blocked.x).abs() + (start.y - blocked.y).abs(), 1);
+    }
+
+    /// The grid checked against a fresh scan of every positioned entity.
+    fn assert_grid_matches(world: &mut World) {
+        let positions: Vec<(Entity, Position, bool)> = world
+            .query::<(Entity, &Position, Option<&Wall>)>()
+            .iter(world)
+            .map(|(e, p, wall)| (e, *p, wall.is_some()))
+            .collect();
+        let grid = world.get_resource::<WorldGrid>().unwrap();
+        for (e, pos, wall) in positions {
+            assert!(entities_at(grid, &pos).contains(&e));
+            if wall {
+                assert_eq!(first_wall_at(grid, &pos), Some(e));
+            }
+        }
+        assert_eq!(crowded_cells(grid).count(), 0);
+    }
+
+    /// Everything the validation, dig and build systems read.
+    fn world_for_digging(attack: bool) -> World {
+        let mut world = world_with_assets(6, 6);
+        world.insert_resource(Score::default());
+        world.insert_resource(Combo::default());
+        world.insert_resource(Achievements::default());
+        world.insert_resource(ChainDig(false));
+        world.insert_resource(PracticeMode(false));
+        world.insert_resource(RockCapacity::default());
+        world.insert_resource(BuildLimit::default());
+        world.insert_resource(AnimatedDig(false));
+        world.insert_resource(AttackByDig(attack));
+        world.insert_resource(Audio::<AudioSource>::default());
+        world.insert_resource(GameAudio::default());
+        world.insert_resource(WrapMode(false));
+        world.insert_resource(CameraShake::default());
+        world.insert_resource(Fonts {
+            ui: Handle::default(),
+        });
+        world.insert_resource(ActionHistory::default());
+        world.insert_resource(GameRng::new(Some(1)));
+        world
+    }
+
+    #[test]
+    fn grid_stays_in_step_through_a_dig_and_a_build() {
+        let mut world = world_for_digging(false);
+        let target = Position { x: 3, y: 2 };
+        let wall = world
+            .spawn()
+            .insert_bundle(SpriteBundle::default())
+            .insert(target)
+            .insert(Wall)
+            .id();
+        let digger = world
+            .spawn()
+            .insert(Position { x: 2, y: 2 })
+            .insert(player(Action::Dig, Direction::Right))
+            .insert(PlayerId(0))
+            .insert(Footprint::square(1))
+            .insert(DigCooldown(ready_timer(0.)))
+            .insert(BuildCooldown(ready_timer(0.)))
+            .id();
+        sync_grid(&mut world);
+        assert_eq!(
+            first_wall_at(world.get_resource().unwrap(), &target),
+            Some(wall)
+        );
+
+        run(&mut world, player_dig_action.system());
+
+        assert!(world.get_entity(wall).is_none());
+        assert!(entities_at(world.get_resource().unwrap(), &target).is_empty());
+        assert_eq!(world.get::<Player>(digger).unwrap().rocks, 1);
+        assert_grid_matches(&mut world);
+
+        world.get_mut::<Player>(digger).unwrap().action = Action::Build;
+        run(&mut world, player_build_action.system());
+
+        let built = first_wall_at(world.get_resource().unwrap(), &target).unwrap();
+        assert_ne!(built, wall);
+        assert_eq!(world.get::<Player>(digger).unwrap().rocks, 0);
+        assert_grid_matches(&mut world);
+    }
+
+    #[test]
+    fn spawn_walls_never_seals_a_player_in() {
+        // A corridor up the left of a 3x3 interior, with the player at the
+        // bottom. A wall halfway up would cut off the top, so only the top
+        // itself may be walled, however the candidates are drawn.
+        for seed in 0..8 {
+            let mut world = world_with_assets(5, 5);
+            world.insert_resource(PracticeMode(false));
+            world.insert_resource(WallDensity { target: 1. });
+            world.insert_resource(TreasureWalls::default());
+            world.insert_resource(GameRng::new(Some(seed)));
+            world.insert_resource(SafeZone { walls_left: 0 });
+            for x in 0..5 {
+                for y in 0..5 {
+                    let position = Position { x, y };
+                    if x == 0 || y == 0 || x == 4 || y == 4 {
+                        world.spawn().insert(position).insert(Wall).insert(Boundary);
+                    } else if x > 1 {
+                        world.spawn().insert(position).insert(Wall);
+                    }
+                }
+            }
+            world
+                .spawn()
+                .insert(Position { x: 1, y: 1 })
+                .insert(player(Action::Idle, Direction::Up));
+            sync_grid(&mut world);
+
+            run(&mut world, spawn_walls.system());
+
+            let grid = world.get_resource::<WorldGrid>().unwrap();
+            assert_eq!(first_wall_at(grid, &Position { x: 1, y: 2 }), None);
+            assert!(first_wall_at(grid, &Position { x: 1, y: 3 }).is_some());
+        }
+    }
+
+    #[test]
+    fn leaving_a_finished_game_despawns_the_arena() {
+        let mut world = World::default();
+        world.insert_resource(State::new(GameState::Playing));
+        let mut stage = SystemStage::parallel();
+        stage
+            .add_system_set(State::<GameState>::get_driver())
+            .add_system_set(
+                SystemSet::on_exit(GameState::GameOver).with_system(despawn_arena.system()),
+            );
+        stage.run(&mut world);
+        let cell = Position { x: 1, y: 1 };
+        world
+            .spawn()
+            .insert(cell)
+            .insert(player(Action::Idle, Direction::Up));
+        world.spawn().insert(cell).insert(Wall);
+        world.spawn().insert(cell).insert(Enemy {
+            kind: EnemyKind::Chaser,
+        });
+        world.spawn().insert(cell).insert(Gem);
+        world.spawn().insert(TrailFade);
+        world.spawn().insert(Debris);
+        let camera = world.spawn().insert(MainCamera).id();
+
+        // The final board stays up behind the game over screen
+        let mut state = world.get_resource_mut::<State<GameState>>().unwrap();
+        state.set(GameState::GameOver).unwrap();
+        stage.run(&mut world);
+        assert_eq!(world.query::<&Wall>().iter(&world).count(), 1);
+
+        let mut state = world.get_resource_mut::<State<GameState>>().unwrap();
+        state.set(GameState::Menu).unwrap();
+        stage.run(&mut world);
+
+        assert_eq!(world.query::<&Player>().iter(&world).count(), 0);
+        assert_eq!(world.query::<&Wall>().iter(&world).count(), 0);
+        assert_eq!(world.query::<&Enemy>().iter(&world).count(), 0);
+        assert_eq!(world.query::<&Gem>().iter(&world).count(), 0);
+        assert_eq!(world.query::<&TrailFade>().iter(&world).count(), 0);
+        assert_eq!(world.query::<&Debris>().iter(&world).count(), 0);
+        assert!(world.get_entity(camera).is_some());
+    }
+
+    #[test]
+    fn digging_a_treasure_wall_pays_its_bonus() {
+        let mut world = world_for_digging(false);
+        let wall = world
+            .spawn()
+            .insert_bundle(SpriteBundle::default())
+            .insert(Position { x: 3, y: 2 })
+            .insert(Wall)
+            .insert(Treasure(25))
+            .id();
+        world
+            .spawn()
+            .insert(Position { x: 2, y: 2 })
+            .insert(player(Action::Dig, Direction::Right))
+            .insert(PlayerId(0))
+            .insert(Footprint::square(1))
+            .insert(DigCooldown(ready_timer(0.)));
+        sync_grid(&mut world);
+
+        run(&mut world, player_dig_action.system());
+
+        assert!(world.get_entity(wall).is_none());
+        // A single dig is worth one point, plus the treasure, but is still one
+        // wall dug
+        let score = world.get_resource::<Score>().unwrap();
+        assert_eq!((score.dug, score.points), (1, 26));
+        let player = score.players[&PlayerId(0)];
+        assert_eq!((player.dug, player.points), (1, 26));
+    }
+
+    #[test]
+    fn digging_at_an_enemy_wears_it_down() {
+        let mut world = world_for_digging(true);
+        let cell = Position { x: 3, y: 2 };
+        // The enemy takes the hits ahead of the wall it shares the cell with
+        let wall = world.spawn().insert(cell).insert(Wall).id();
+        let enemy = world
+            .spawn()
+            .insert(cell)
+            .insert(Enemy {
+                kind: EnemyKind::Chaser,
+            })
+            .insert(EnemyHealth(2))
+            .id();
+        let digger = world
+            .spawn()
+            .insert(Position { x: 2, y: 2 })
+            .insert(player(Action::Dig, Direction::Right))
+            .insert(PlayerId(0))
+            .insert(Footprint::square(1))
+            .insert(DigCooldown(ready_timer(0.)))
+            .id();
+        sync_grid(&mut world);
+
+        for hit_points in [1, 0] {
+            world.get_mut::<Player>(digger).unwrap().action = Action::Dig;
+            run(&mut world, validate_player_action.system());
+            assert_eq!(world.get::<Player>(digger).unwrap().action, Action::Dig);
+            run(&mut world, player_dig_action.system());
+            match hit_points {
+                0 => assert!(world.get_entity(enemy).is_none()),
+                _ => assert_eq!(world.get::<EnemyHealth>(enemy).unwrap().0, hit_points),
+            }
+        }
+        assert!(world.get_entity(wall).is_some());
+        assert_eq!(world.get::<Player>(digger).unwrap().rocks, 0);
+    }
+
+    #[test]
+    fn a_sliding_boulder_runs_until_it_hits_an_enemy() {
+        let mut world = world_with_arena(8, 4);
+        world.insert_resource(WrapMode(false));
+        world.insert_resource(Timesteps::default());
+        let boulder = world
+            .spawn()
+            .insert(Position { x: 1, y: 1 })
+            .insert(Boulder)
+            .insert(Sliding {
+                direction: Direction::Right,
+            })
+            .id();
+        let enemy = world
+            .spawn()
+            .insert(Position { x: 5, y: 1 })
+            .insert(Enemy {
+                kind: EnemyKind::Chaser,
+            })
+            .insert(EnemyHealth(2))
+            .id();
+        sync_grid(&mut world);
+
+        for _ in 0..5 {
+            run(&mut world, boulder::slide_system.system());
+        }
+        assert_eq!(
+            *world.get::<Position>(boulder).unwrap(),
+            Position { x: 4, y: 1 }
+        );
+        assert!(world.get::<Sliding>(boulder).is_none());
+        assert_eq!(world.get::<EnemyHealth>(enemy).unwrap().0, 1);
+    }
+
+    #[test]
+    fn a_long_fall_costs_lives_past_the_safe_drop() {
+        let mut world = world_with_arena(4, 8);
+        world.insert_resource(FallDamageConfig {
+            enabled: true,
+            ..Default::default()
+        });
+        world.insert_resource(Timesteps::default());
+        world.insert_resource(State::new(GameState::Playing));
+        world.insert_resource(Lives::default());
+        let faller = world
+            .spawn()
+            .insert(Position { x: 1, y: 5 })
+            .insert(player(Action::Idle, Direction::Down))
+            .insert(Footprint::square(1))
+            .id();
+        world.spawn().insert(Position { x: 2, y: 1 }).insert(Wall);
+        let hopper = world
+            .spawn()
+            .insert(Position { x: 2, y: 3 })
+            .insert(player(Action::Idle, Direction::Down))
+            .insert(Footprint::square(1))
+            .id();
+        sync_grid(&mut world);
+
+        for _ in 0..8 {
+            run(&mut world, player_gravity.system());
+        }
+        // Five cells down to the floor is two past the safe three; the one
+        // cell drop onto the wall costs nothing
+        assert_eq!(
+            *world.get::<Position>(faller).unwrap(),
+            Position { x: 1, y: 0 }
+        );
+        assert_eq!(
+            *world.get::<Position>(hopper).unwrap(),
+            Position { x: 2, y: 2 }
+        );
+        assert_eq!(world.get_resource::<Lives>().unwrap().0, 1);
+    }
+
+    #[test]
+    fn tiles_fit_a_tall_arena() {
+        let window = Window::new(
+            bevy::window::WindowId::primary(),
+            &WindowDescriptor::default(),
+            800,
+            600,
+            1.0,
+            None,
+        );
+        let arena = ArenaConfig {
+            width: 20,
+            height: 40,
+        };
+        let follow = CameraFollow::default();
+        assert_eq!(
+            tile_size(&window, &arena, ScalingMode::Stretch, &follow),
+            Vec2::new(40., 15.)
+        );
+        // Square tiles take the tighter axis, leaving the rest of the width empty
+        assert_eq!(
+            tile_size(&window, &arena, ScalingMode::Square, &follow),
+            Vec2::splat(15.)
+        );
+    }
 }
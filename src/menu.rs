@@ -0,0 +1,530 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use crate::difficulty::Difficulty;
+use crate::highscore::HighScore;
+use crate::localization::Localization;
+use crate::palette::Palette;
+use crate::save::{PendingLoad, SaveSlot, SaveSlots};
+use crate::share::{decode_share_code, ImportShareCode, ShareCode};
+use crate::{Fonts, GameState, Materials};
+
+const MENU_COLOR: Color = Color::WHITE;
+const SELECTED_COLOR: Color = Color::rgb(1.0, 0.85, 0.2);
+/// A corrupt or outdated save on the load list.
+const UNLOADABLE_COLOR: Color = Color::GRAY;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum MenuItem {
+    Start,
+    /// Lists the saves to pick one up from.
+    Load,
+    /// Cycles through the difficulties rather than leaving the menu.
+    Difficulty,
+    /// Cycles through the colour schemes, recolouring the menu behind it.
+    Palette,
+    /// Cycles through the translations in the locale directory.
+    Language,
+    /// Shows the next game's share code, and takes one typed in to replay.
+    Code,
+    Editor,
+    Quit,
+}
+
+/// Top to bottom, as drawn.
+const MENU_ITEMS: [MenuItem; 8] = [
+    MenuItem::Start,
+    MenuItem::Load,
+    MenuItem::Difficulty,
+    MenuItem::Palette,
+    MenuItem::Language,
+    MenuItem::Code,
+    MenuItem::Editor,
+    MenuItem::Quit,
+];
+
+impl MenuItem {
+    fn text(
+        &self,
+        localization: &Localization,
+        difficulty: Difficulty,
+        palette: Palette,
+        code: &str,
+    ) -> String {
+        match self {
+            MenuItem::Start => localization.t("menu.start").to_string(),
+            MenuItem::Load => localization.t("menu.load").to_string(),
+            MenuItem::Difficulty => format!(
+                "{}: {}",
+                localization.t("menu.difficulty"),
+                localization.t(difficulty.label())
+            ),
+            MenuItem::Palette => format!(
+                "{}: {}",
+                localization.t("menu.palette"),
+                localization.t(palette.label())
+            ),
+            MenuItem::Language => format!(
+                "{}: {}",
+                localization.t("menu.language"),
+                localization.t("language.name")
+            ),
+            MenuItem::Code => format!("{}: {}", localization.t("menu.code"), code),
+            MenuItem::Editor => localization.t("menu.editor").to_string(),
+            MenuItem::Quit => localization.t("menu.quit").to_string(),
+        }
+    }
+}
+
+/// Index into `MENU_ITEMS` of the highlighted option.
+#[derive(Default)]
+struct MenuSelection(usize);
+
+struct MenuUi;
+
+/// The text of one option, at its index in `MENU_ITEMS`.
+struct MenuOption(usize);
+
+/// The high score line under the options.
+struct MenuBest;
+
+/// A share code being typed in, while the code option is edited.
+#[derive(Default)]
+struct CodeEntry {
+    text: Option<String>,
+    /// Set when the last code entered didn't decode, until another is started.
+    invalid: bool,
+}
+
+/// What the code option shows after its label.
+fn code_text(localization: &Localization, entry: &CodeEntry, share: &ShareCode) -> String {
+    match (&entry.text, &share.0) {
+        (Some(text), _) => format!("{}_", text),
+        (None, _) if entry.invalid => localization.t("menu.code_invalid").to_string(),
+        (None, Some(code)) => code.clone(),
+        (None, None) => "-".to_string(),
+    }
+}
+
+fn best_text(localization: &Localization, high_score: &HighScore) -> String {
+    format!("{}: {}", localization.t("hud.best"), high_score.0)
+}
+
+fn menu_text(fonts: &Fonts, value: &str, color: Color) -> TextBundle {
+    TextBundle {
+        text: Text::with_section(
+            value,
+            TextStyle {
+                font: fonts.ui.clone(),
+                font_size: 24.0,
+                color,
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    }
+}
+
+fn spawn_menu(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    materials: Res<Materials>,
+    localization: Res<Localization>,
+    high_score: Res<HighScore>,
+    difficulty: Res<Difficulty>,
+    palette: Res<Palette>,
+    share: Res<ShareCode>,
+    mut selection: ResMut<MenuSelection>,
+    mut entry: ResMut<CodeEntry>,
+) {
+    selection.0 = 0;
+    *entry = CodeEntry::default();
+    let code = code_text(&localization, &entry, &share);
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                // UI children stack upwards by default
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.overlay_material.clone(),
+            ..Default::default()
+        })
+        .insert(MenuUi)
+        .with_children(|parent| {
+            for (index, item) in MENU_ITEMS.iter().enumerate() {
+                parent
+                    .spawn_bundle(menu_text(
+                        &fonts,
+                        &item.text(&localization, *difficulty, *palette, &code),
+                        MENU_COLOR,
+                    ))
+                    .insert(MenuOption(index));
+            }
+            parent
+                .spawn_bundle(menu_text(
+                    &fonts,
+                    &best_text(&localization, &high_score),
+                    MENU_COLOR,
+                ))
+                .insert(MenuBest);
+        });
+}
+
+/// Up and Down move the highlight, wrapping at either end; Enter picks it.
+/// Left and Right also change the difficulty, palette or language while
+/// highlighted.
+fn menu_navigation(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut selection: ResMut<MenuSelection>,
+    mut difficulty: ResMut<Difficulty>,
+    mut palette: ResMut<Palette>,
+    mut localization: ResMut<Localization>,
+    mut state: ResMut<State<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let count = MENU_ITEMS.len();
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        selection.0 = (selection.0 + count - 1) % count;
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        selection.0 = (selection.0 + 1) % count;
+    }
+    let item = MENU_ITEMS[selection.0];
+    let left = keyboard_input.just_pressed(KeyCode::Left);
+    let right = keyboard_input.just_pressed(KeyCode::Right);
+    match item {
+        MenuItem::Difficulty if left => *difficulty = difficulty.previous(),
+        MenuItem::Difficulty if right => *difficulty = difficulty.next(),
+        MenuItem::Palette if left => *palette = palette.previous(),
+        MenuItem::Palette if right => *palette = palette.next(),
+        MenuItem::Language if left => localization.cycle_language(false),
+        MenuItem::Language if right => localization.cycle_language(true),
+        _ => {}
+    }
+    if !keyboard_input.just_pressed(KeyCode::Return) {
+        return;
+    }
+    match item {
+        MenuItem::Start => state.set(GameState::Playing).unwrap(),
+        MenuItem::Load => {
+            // Or the list would take the same press to load its first save
+            keyboard_input.reset(KeyCode::Return);
+            state.set(GameState::LoadGame).unwrap();
+        }
+        MenuItem::Difficulty => *difficulty = difficulty.next(),
+        MenuItem::Palette => *palette = palette.next(),
+        MenuItem::Language => localization.cycle_language(true),
+        // Typed into by `enter_share_code`
+        MenuItem::Code => {}
+        MenuItem::Editor => state.set(GameState::Editor).unwrap(),
+        MenuItem::Quit => exit.send(AppExit),
+    }
+}
+
+/// With the code option highlighted, Enter starts typing a code and Enter
+/// again replays it, while Escape gives up. Moving off the option drops
+/// whatever was typed.
+fn enter_share_code(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    selection: Res<MenuSelection>,
+    mut entry: ResMut<CodeEntry>,
+    mut imports: EventWriter<ImportShareCode>,
+) {
+    // Drained every frame, so nothing typed elsewhere turns up in a code
+    let typed: String = characters
+        .iter()
+        .map(|event| event.char)
+        .filter(char::is_ascii_alphanumeric)
+        .collect();
+    if MENU_ITEMS[selection.0] != MenuItem::Code {
+        if entry.text.is_some() {
+            entry.text = None;
+        }
+        return;
+    }
+    let enter = keyboard_input.just_pressed(KeyCode::Return);
+    let entry = &mut *entry;
+    let text = match entry.text.as_mut() {
+        Some(text) => text,
+        None => {
+            if enter {
+                entry.text = Some(String::new());
+                entry.invalid = false;
+            }
+            return;
+        }
+    };
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        entry.text = None;
+    } else if enter {
+        match decode_share_code(text) {
+            Ok(config) => imports.send(ImportShareCode(config)),
+            Err(e) => {
+                warn!("Ignoring share code {:?}: {}", text, e);
+                entry.invalid = true;
+            }
+        }
+        entry.text = None;
+    } else {
+        if keyboard_input.just_pressed(KeyCode::Back) {
+            text.pop();
+        }
+        text.push_str(&typed);
+    }
+}
+
+/// Also keeps the menu text current, since the difficulty, palette, language
+/// and share code change in place.
+fn highlight_menu(
+    selection: Res<MenuSelection>,
+    difficulty: Res<Difficulty>,
+    palette: Res<Palette>,
+    localization: Res<Localization>,
+    high_score: Res<HighScore>,
+    (entry, share): (Res<CodeEntry>, Res<ShareCode>),
+    mut options: Query<(&MenuOption, &mut Text)>,
+    mut best: Query<&mut Text, (With<MenuBest>, Without<MenuOption>)>,
+) {
+    if localization.is_changed() {
+        for mut text in best.iter_mut() {
+            text.sections[0].value = best_text(&localization, &high_score);
+        }
+    }
+    let changed = difficulty.is_changed()
+        || palette.is_changed()
+        || localization.is_changed()
+        || entry.is_changed()
+        || share.is_changed();
+    let code = code_text(&localization, &entry, &share);
+    for (option, mut text) in options.iter_mut() {
+        if changed {
+            text.sections[0].value =
+                MENU_ITEMS[option.0].text(&localization, *difficulty, *palette, &code);
+        }
+        let color = if option.0 == selection.0 {
+            SELECTED_COLOR
+        } else {
+            MENU_COLOR
+        };
+        if text.sections[0].style.color != color {
+            text.sections[0].style.color = color;
+        }
+    }
+}
+
+fn despawn_menu(mut commands: Commands, menus: Query<Entity, With<MenuUi>>) {
+    for e in menus.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+/// Index into `SaveSlots::slots` of the highlighted save.
+#[derive(Default)]
+struct LoadSelection(usize);
+
+struct LoadUi;
+
+/// One save's line, at its index in `SaveSlots::slots`.
+struct LoadOption(usize);
+
+/// `YYYY-MM-DD HH:MM` in UTC, from seconds since the Unix epoch.
+fn date_text(secs: u64) -> String {
+    let minutes = secs % 86_400 / 60;
+    // Days to a civil date, after Howard Hinnant's `civil_from_days`
+    let z = secs / 86_400 + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+fn slot_text(localization: &Localization, slot: &SaveSlot) -> String {
+    match &slot.header {
+        Ok(header) => format!(
+            "{}  {}: {}  {}",
+            slot.name,
+            localization.t("load.score"),
+            header.score,
+            date_text(header.saved_at)
+        ),
+        Err(_) => format!("{}  ({})", slot.name, localization.t("load.unloadable")),
+    }
+}
+
+fn spawn_load_list(mut slots: ResMut<SaveSlots>, mut selection: ResMut<LoadSelection>) {
+    selection.0 = 0;
+    // Drawn by `draw_load_list`, as it is again whenever a save is deleted
+    slots.scan();
+}
+
+fn draw_load_list(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    materials: Res<Materials>,
+    localization: Res<Localization>,
+    slots: Res<SaveSlots>,
+    lists: Query<Entity, With<LoadUi>>,
+) {
+    if !slots.is_changed() {
+        return;
+    }
+    for e in lists.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.overlay_material.clone(),
+            ..Default::default()
+        })
+        .insert(LoadUi)
+        .with_children(|parent| {
+            if slots.slots.is_empty() {
+                parent.spawn_bundle(menu_text(
+                    &fonts,
+                    localization.t("load.empty"),
+                    UNLOADABLE_COLOR,
+                ));
+            }
+            for (index, slot) in slots.slots.iter().enumerate() {
+                parent
+                    .spawn_bundle(menu_text(
+                        &fonts,
+                        &slot_text(&localization, slot),
+                        MENU_COLOR,
+                    ))
+                    .insert(LoadOption(index));
+            }
+            parent.spawn_bundle(menu_text(&fonts, localization.t("load.hint"), MENU_COLOR));
+        });
+}
+
+/// Up and Down move the highlight; Enter loads the save and plays on from it,
+/// Delete removes it and Escape goes back to the menu. Saves that can't be
+/// loaded can still be deleted.
+fn load_list_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut selection: ResMut<LoadSelection>,
+    mut slots: ResMut<SaveSlots>,
+    mut pending: ResMut<PendingLoad>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        state.set(GameState::Menu).unwrap();
+        return;
+    }
+    let count = slots.slots.len();
+    if count == 0 {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        selection.0 = (selection.0 + count - 1) % count;
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        selection.0 = (selection.0 + 1) % count;
+    }
+    let slot = &slots.slots[selection.0.min(count - 1)];
+    let name = slot.name.clone();
+    if keyboard_input.just_pressed(KeyCode::Delete) {
+        if let Err(e) = slots.delete(&name) {
+            warn!("Could not delete slot {}: {}", name, e);
+        }
+        selection.0 = selection.0.min(slots.slots.len().saturating_sub(1));
+    } else if keyboard_input.just_pressed(KeyCode::Return) && slot.header.is_ok() {
+        match slots.load(&name) {
+            Ok(snapshot) => {
+                pending.0 = Some(snapshot);
+                state.set(GameState::Playing).unwrap();
+            }
+            Err(e) => {
+                warn!("Could not load slot {}: {}", name, e);
+                // Shows it as unloadable from now on
+                slots.scan();
+            }
+        }
+    }
+}
+
+fn highlight_load_list(
+    selection: Res<LoadSelection>,
+    slots: Res<SaveSlots>,
+    mut options: Query<(&LoadOption, &mut Text)>,
+) {
+    for (option, mut text) in options.iter_mut() {
+        let loadable = slots
+            .slots
+            .get(option.0)
+            .map_or(false, |slot| slot.header.is_ok());
+        let color = if option.0 == selection.0 {
+            SELECTED_COLOR
+        } else if loadable {
+            MENU_COLOR
+        } else {
+            UNLOADABLE_COLOR
+        };
+        if text.sections[0].style.color != color {
+            text.sections[0].style.color = color;
+        }
+    }
+}
+
+fn despawn_load_list(mut commands: Commands, lists: Query<Entity, With<LoadUi>>) {
+    for e in lists.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+pub(crate) struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<MenuSelection>()
+            .init_resource::<CodeEntry>()
+            .add_system_set(SystemSet::on_enter(GameState::Menu).with_system(spawn_menu.system()))
+            .add_system_set(
+                SystemSet::on_update(GameState::Menu)
+                    .with_system(menu_navigation.system())
+                    .with_system(enter_share_code.system())
+                    .with_system(highlight_menu.system()),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Menu).with_system(despawn_menu.system()))
+            .init_resource::<LoadSelection>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::LoadGame).with_system(spawn_load_list.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::LoadGame)
+                    .with_system(load_list_input.system().label("load_input"))
+                    .with_system(draw_load_list.system().after("load_input"))
+                    .with_system(highlight_load_list.system().after("load_input")),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::LoadGame).with_system(despawn_load_list.system()),
+            );
+    }
+}
@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+
+use crate::hud::{HudAnchor, HudElement};
+use crate::{ArenaConfig, Materials, Player, Position, Wall};
+
+/// Side of one cell on the minimap, in pixels.
+const DOT_SIZE: f32 = 4.0;
+/// Seconds between redraws; the board changes slowly, so there's no need to
+/// touch every dot each frame.
+const REFRESH_SECONDS: f32 = 0.2;
+
+pub(crate) struct Minimap;
+
+/// A pooled dot, reused across redraws and hidden while not needed.
+pub(crate) struct MinimapDot;
+
+pub(crate) fn spawn_minimap(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    materials: Res<Materials>,
+) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(
+                    Val::Px(arena.width as f32 * DOT_SIZE),
+                    Val::Px(arena.height as f32 * DOT_SIZE),
+                ),
+                ..Default::default()
+            },
+            material: materials.minimap_material.clone(),
+            ..Default::default()
+        })
+        .insert(Minimap)
+        .insert(HudElement {
+            anchor: HudAnchor::BottomRight,
+            order: 0,
+        });
+}
+
+fn dot_style(pos: &Position) -> Style {
+    Style {
+        size: Size::new(Val::Px(DOT_SIZE), Val::Px(DOT_SIZE)),
+        position_type: PositionType::Absolute,
+        position: Rect {
+            left: Val::Px(pos.x as f32 * DOT_SIZE),
+            bottom: Val::Px(pos.y as f32 * DOT_SIZE),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+pub(crate) fn render_minimap(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut since_refresh: Local<f32>,
+    materials: Res<Materials>,
+    minimaps: Query<Entity, With<Minimap>>,
+    cells: Query<(&Position, Option<&Player>), Or<(With<Wall>, With<Player>)>>,
+    mut dots: Query<(&mut Style, &mut Handle<ColorMaterial>, &mut Visible), With<MinimapDot>>,
+) {
+    *since_refresh += time.delta_seconds();
+    if *since_refresh < REFRESH_SECONDS {
+        return;
+    }
+    *since_refresh = 0.;
+    let minimap = match minimaps.iter().next() {
+        Some(minimap) => minimap,
+        None => return,
+    };
+    // Players last so they draw over walls
+    let mut marks: Vec<(Position, bool)> = cells
+        .iter()
+        .map(|(pos, player)| (*pos, player.is_some()))
+        .collect();
+    marks.sort_by_key(|(_, player)| *player);
+    let material = |player: bool| {
+        if player {
+            materials.player_material.clone()
+        } else {
+            materials.wall_material.clone()
+        }
+    };
+    let mut marks = marks.into_iter();
+    for (mut style, mut handle, mut visible) in dots.iter_mut() {
+        match marks.next() {
+            Some((pos, player)) => {
+                *style = dot_style(&pos);
+                *handle = material(player);
+                visible.is_visible = true;
+            }
+            None => visible.is_visible = false,
+        }
+    }
+    let extra: Vec<_> = marks.collect();
+    if !extra.is_empty() {
+        commands.entity(minimap).with_children(|parent| {
+            for (pos, player) in extra {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: dot_style(&pos),
+                        material: material(player),
+                        ..Default::default()
+                    })
+                    .insert(MinimapDot);
+            }
+        });
+    }
+}
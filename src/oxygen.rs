@@ -0,0 +1,213 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::hud::{HudAnchor, HudElement};
+use crate::{
+    open_interior_cells, ArenaConfig, Footprint, GameRng, GameState, Materials, Paused, PlayerId,
+    Position, Size, SpawnClaims, TimeScale, WorldSteps,
+};
+
+/// Air pockets and bubbles scattered over the arena at the start of each game.
+const AIR_POCKETS: usize = 2;
+const AIR_BUBBLES: usize = 4;
+/// Oxygen used per second.
+const OXYGEN_DRAIN: f32 = 1.0;
+/// Oxygen per second regained while standing in a pocket.
+const POCKET_REFILL: f32 = 5.0;
+/// Oxygen a bubble gives back when collected.
+const BUBBLE_REFILL: f32 = 10.0;
+/// Size of the HUD bar at full oxygen, in pixels.
+const BAR_WIDTH: f32 = 120.0;
+const BAR_HEIGHT: f32 = 8.0;
+
+/// Survival variant: players run out of air over time and the game ends when
+/// it's gone. Enabled from `config.toml`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct OxygenMode {
+    pub enabled: bool,
+    /// Seconds of air in a full tank.
+    pub capacity: f32,
+}
+
+impl Default for OxygenMode {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 30.0,
+        }
+    }
+}
+
+/// Air left in the shared tank.
+#[derive(Default)]
+pub(crate) struct Oxygen(pub f32);
+
+/// Somewhere to breathe. Pockets stay put and refill the tank while a player
+/// stands in them; bubbles are used up when collected.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub(crate) enum Air {
+    Pocket,
+    Bubble,
+}
+
+pub(crate) struct OxygenBar;
+
+struct OxygenFill;
+
+fn spawn_air(
+    mut commands: Commands,
+    mode: Res<OxygenMode>,
+    mut oxygen: ResMut<Oxygen>,
+    arena: Res<ArenaConfig>,
+    materials: Res<Materials>,
+    mut rng: ResMut<GameRng>,
+    mut claims: ResMut<SpawnClaims>,
+) {
+    oxygen.0 = mode.capacity;
+    if !mode.enabled {
+        return;
+    }
+    let mut open = open_interior_cells(&arena, &claims);
+    let kinds = std::iter::repeat(Air::Pocket)
+        .take(AIR_POCKETS)
+        .chain(std::iter::repeat(Air::Bubble).take(AIR_BUBBLES));
+    for air in kinds {
+        if open.is_empty() {
+            break;
+        }
+        let index = rng.index(open.len());
+        let position = open.swap_remove(index);
+        claims.claim(position);
+        let (material, size) = match air {
+            Air::Pocket => (materials.air_pocket_material.clone(), 0.9),
+            Air::Bubble => (materials.air_bubble_material.clone(), 0.3),
+        };
+        commands
+            .spawn_bundle(SpriteBundle {
+                material,
+                sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                ..Default::default()
+            })
+            .insert(air)
+            .insert(position)
+            .insert(Size::square(size));
+    }
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: bevy::math::Size::new(Val::Px(BAR_WIDTH), Val::Px(BAR_HEIGHT)),
+                ..Default::default()
+            },
+            material: materials.minimap_material.clone(),
+            ..Default::default()
+        })
+        .insert(OxygenBar)
+        .insert(HudElement {
+            anchor: HudAnchor::TopCenter,
+            order: 1,
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: bevy::math::Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                        ..Default::default()
+                    },
+                    material: materials.air_bubble_material.clone(),
+                    ..Default::default()
+                })
+                .insert(OxygenFill);
+        });
+}
+
+fn tick_oxygen(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mode: Res<OxygenMode>,
+    mut oxygen: ResMut<Oxygen>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if !mode.enabled || paused.0 {
+        return;
+    }
+    oxygen.0 = (oxygen.0 - OXYGEN_DRAIN * scale.delta(&time) as f32).max(0.);
+    if oxygen.0 == 0. {
+        state.overwrite_set(GameState::GameOver).unwrap();
+    }
+}
+
+fn refill_oxygen(
+    mut commands: Commands,
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mode: Res<OxygenMode>,
+    mut oxygen: ResMut<Oxygen>,
+    players: Query<(&Position, &Footprint), With<PlayerId>>,
+    air: Query<(Entity, &Position, &Air)>,
+) {
+    if !mode.enabled || paused.0 {
+        return;
+    }
+    let mut collected: Vec<Entity> = Vec::new();
+    for (player, footprint) in players.iter() {
+        for (e, pos, air) in air.iter() {
+            if !footprint.covers(player, pos) || collected.contains(&e) {
+                continue;
+            }
+            match air {
+                Air::Pocket => oxygen.0 += POCKET_REFILL * scale.delta(&time) as f32,
+                Air::Bubble => {
+                    oxygen.0 += BUBBLE_REFILL;
+                    commands.entity(e).despawn();
+                    collected.push(e);
+                }
+            }
+        }
+    }
+    oxygen.0 = oxygen.0.min(mode.capacity);
+}
+
+fn update_oxygen_bar(
+    mode: Res<OxygenMode>,
+    oxygen: Res<Oxygen>,
+    mut fills: Query<&mut Style, With<OxygenFill>>,
+) {
+    if !oxygen.is_changed() {
+        return;
+    }
+    let width = Val::Percent(100. * oxygen.0 / mode.capacity);
+    for mut style in fills.iter_mut() {
+        style.size.width = width;
+    }
+}
+
+pub(crate) struct OxygenPlugin;
+
+impl Plugin for OxygenPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Oxygen>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing)
+                    .with_system(spawn_air.system().label("air").after("portals")),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        refill_oxygen
+                            .system()
+                            .label(WorldSteps::Air)
+                            .after(WorldSteps::Drowning),
+                    )
+                    .with_system(
+                        tick_oxygen
+                            .system()
+                            .label(WorldSteps::Oxygen)
+                            .after(WorldSteps::Air),
+                    )
+                    .with_system(update_oxygen_bar.system()),
+            );
+    }
+}
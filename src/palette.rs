@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+
+use crate::{Materials, PlayerAppearance, PlayerId, WallAppearance};
+
+/// Colour scheme for the players and walls, picked from the menu. Default
+/// uses the colours from `config.toml`; the others replace them with ones
+/// that stay distinct under red-green colour blindness or on poor displays.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Palette {
+    Default,
+    Deuteranopia,
+    HighContrast,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::Default
+    }
+}
+
+/// The colours a palette decides.
+pub(crate) struct PaletteColors {
+    pub player: Color,
+    pub second_player: Color,
+    pub wall: Color,
+    pub boundary: Color,
+}
+
+impl Palette {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Palette::Default => Palette::Deuteranopia,
+            Palette::Deuteranopia => Palette::HighContrast,
+            Palette::HighContrast => Palette::Default,
+        }
+    }
+
+    pub(crate) fn previous(self) -> Self {
+        match self {
+            Palette::Default => Palette::HighContrast,
+            Palette::Deuteranopia => Palette::Default,
+            Palette::HighContrast => Palette::Deuteranopia,
+        }
+    }
+
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Palette::Default => "palette.default",
+            Palette::Deuteranopia => "palette.deuteranopia",
+            Palette::HighContrast => "palette.high_contrast",
+        }
+    }
+
+    pub(crate) fn colors(self, player: &PlayerAppearance, wall: &WallAppearance) -> PaletteColors {
+        match self {
+            Palette::Default => PaletteColors {
+                player: player.color,
+                second_player: Color::rgb(0.3, 0.6, 0.9),
+                wall: wall.color,
+                boundary: Color::rgb(1., 0., 0.),
+            },
+            // Blue against orange and yellow, which red-green colour blind
+            // players still tell apart
+            Palette::Deuteranopia => PaletteColors {
+                player: Color::rgb(0.95, 0.9, 0.25),
+                second_player: Color::rgb(0.35, 0.7, 0.9),
+                wall: Color::rgb(0., 0.45, 0.7),
+                boundary: Color::rgb(0.8, 0.4, 0.),
+            },
+            Palette::HighContrast => PaletteColors {
+                player: Color::rgb(1., 1., 0.),
+                second_player: Color::rgb(0., 1., 1.),
+                wall: Color::WHITE,
+                boundary: Color::rgb(0.5, 0.5, 0.5),
+            },
+        }
+    }
+}
+
+/// Recolours the existing materials when the palette changes, so everything
+/// already drawn with them switches over at once. Player sprites are tinted
+/// rather than drawn with a material, so they're recoloured directly.
+fn apply_palette(
+    palette: Res<Palette>,
+    player: Res<PlayerAppearance>,
+    wall: Res<WallAppearance>,
+    handles: Res<Materials>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut players: Query<(&PlayerId, &mut TextureAtlasSprite)>,
+) {
+    if !palette.is_changed() {
+        return;
+    }
+    let colors = palette.colors(&player, &wall);
+    let mut preview = colors.wall;
+    preview.set_a(0.35);
+    for (handle, color) in [
+        (&handles.player_material, colors.player),
+        (&handles.second_player_material, colors.second_player),
+        (&handles.wall_material, colors.wall),
+        (&handles.boundary_material, colors.boundary),
+        (&handles.preview_material, preview),
+    ] {
+        if let Some(material) = materials.get_mut(handle) {
+            material.color = color;
+        }
+    }
+    for (id, mut sprite) in players.iter_mut() {
+        sprite.color = match id.0 {
+            0 => colors.player,
+            _ => colors.second_player,
+        };
+    }
+}
+
+pub(crate) struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Palette>()
+            .add_system(apply_palette.system());
+    }
+}
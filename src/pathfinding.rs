@@ -0,0 +1,88 @@
+use bevy::utils::{HashMap, HashSet};
+use std::collections::VecDeque;
+
+use crate::grid::{first_wall_at, WorldGrid};
+use crate::{ArenaConfig, Direction, Position};
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
+/// Breadth-first search over wall-free cells, returning the moves that reach the
+/// nearest cell accepted by `goal`. The path is empty when `start` is already a goal.
+pub(crate) fn find_path(
+    grid: &WorldGrid,
+    arena: &ArenaConfig,
+    start: Position,
+    goal: impl Fn(&Position) -> bool,
+) -> Option<Vec<Direction>> {
+    let mut came_from: HashMap<Position, (Position, Direction)> = HashMap::default();
+    let mut frontier = VecDeque::new();
+    frontier.push_back(start);
+    while let Some(current) = frontier.pop_front() {
+        if goal(&current) {
+            let mut path = Vec::new();
+            let mut cell = current;
+            while let Some((previous, direction)) = came_from.get(&cell) {
+                path.push(*direction);
+                cell = *previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for direction in DIRECTIONS.iter() {
+            let next = current.translated(*direction);
+            if next == start
+                || came_from.contains_key(&next)
+                || !arena.contains(&next)
+                || first_wall_at(grid, &next).is_some()
+            {
+                continue;
+            }
+            came_from.insert(next, (current, *direction));
+            frontier.push_back(next);
+        }
+    }
+    None
+}
+
+/// Every cell reachable from `start` without crossing a wall or `blocked`.
+pub(crate) fn reachable_cells(
+    grid: &WorldGrid,
+    arena: &ArenaConfig,
+    start: Position,
+    blocked: Option<Position>,
+) -> HashSet<Position> {
+    let mut seen = HashSet::default();
+    seen.insert(start);
+    let mut frontier = vec![start];
+    while let Some(current) = frontier.pop() {
+        for direction in DIRECTIONS.iter() {
+            let next = current.translated(*direction);
+            if seen.contains(&next)
+                || Some(next) == blocked
+                || !arena.contains(&next)
+                || first_wall_at(grid, &next).is_some()
+            {
+                continue;
+            }
+            seen.insert(next);
+            frontier.push(next);
+        }
+    }
+    seen
+}
+
+/// The direction from `pos` towards an adjacent cell accepted by `target`, if any.
+pub(crate) fn adjacent_direction(
+    pos: &Position,
+    target: impl Fn(&Position) -> bool,
+) -> Option<Direction> {
+    DIRECTIONS
+        .iter()
+        .copied()
+        .find(|direction| target(&pos.translated(*direction)))
+}
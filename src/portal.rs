@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+
+use crate::grid::{first_wall_at, WorldGrid};
+use crate::{
+    open_interior_cells, ArenaConfig, Footprint, GameRng, GameState, Materials, MoveAnimation,
+    Player, Position, Size, SpawnClaims,
+};
+
+/// Linked pairs of portals scattered over the arena at the start of each game.
+const PORTAL_PAIRS: u32 = 2;
+
+/// One end of a portal. Stepping onto it moves the player to the other portal
+/// with the same `id`.
+pub(crate) struct Portal {
+    id: u32,
+}
+
+/// Where a player last came out of a portal. Standing on the exit doesn't send
+/// them straight back; they have to step off first.
+pub(crate) struct PortalArrival(Position);
+
+fn spawn_portals(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    materials: Res<Materials>,
+    mut rng: ResMut<GameRng>,
+    mut claims: ResMut<SpawnClaims>,
+) {
+    let mut open = open_interior_cells(&arena, &claims);
+    for id in 0..PORTAL_PAIRS {
+        if open.len() < 2 {
+            break;
+        }
+        for _ in 0..2 {
+            let index = rng.index(open.len());
+            let position = open.swap_remove(index);
+            claims.claim(position);
+            claims.claim(position);
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: materials.portal_material.clone(),
+                    sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                    ..Default::default()
+                })
+                .insert(Portal { id })
+                .insert(position)
+                .insert(Size::square(0.9));
+        }
+    }
+}
+
+/// Runs after players move each action tick. Whichever cell of a player steps
+/// onto a portal comes out on the far one, and a portal with no room for the
+/// whole player at its far end goes nowhere.
+pub(crate) fn portal_travel(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    grid: Res<WorldGrid>,
+    portals: Query<(Entity, &Portal, &Position), Without<Player>>,
+    mut players: Query<(Entity, &mut Position, &Footprint, Option<&PortalArrival>), With<Player>>,
+) {
+    for (e, mut pos, footprint, arrival) in players.iter_mut() {
+        if let Some(arrival) = arrival {
+            if arrival.0 == *pos {
+                continue;
+            }
+            commands.entity(e).remove::<PortalArrival>();
+        }
+        let found = portals.iter().find(|(_, _, p)| footprint.covers(&pos, p));
+        let (entrance, portal, entered) = match found {
+            Some((entrance, portal, entered)) => (entrance, portal, *entered),
+            None => continue,
+        };
+        let exit = portals
+            .iter()
+            .find(|(other, linked, _)| *other != entrance && linked.id == portal.id)
+            .map(|(_, _, exit)| Position {
+                x: pos.x + exit.x - entered.x,
+                y: pos.y + exit.y - entered.y,
+            })
+            .filter(|exit| {
+                footprint
+                    .cells(exit)
+                    .iter()
+                    .all(|cell| arena.contains(cell) && first_wall_at(&grid, cell).is_none())
+            });
+        if let Some(exit) = exit {
+            *pos = exit;
+            // Sliding across the arena would look wrong, so travel just jumps
+            commands
+                .entity(e)
+                .remove::<MoveAnimation>()
+                .insert(PortalArrival(exit));
+        }
+    }
+}
+
+pub(crate) struct PortalPlugin;
+
+impl Plugin for PortalPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing)
+                .with_system(spawn_portals.system().label("portals").after("boulders")),
+        );
+    }
+}
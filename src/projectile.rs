@@ -0,0 +1,124 @@
+use bevy::ecs::system::Command;
+use bevy::prelude::*;
+
+use crate::enemy::{Enemy, Stunned};
+use crate::grid::{entities_at, first_wall_at, WorldGrid};
+use crate::{
+    ArenaConfig, Direction, Materials, Player, Position, PracticeMode, Size, Timesteps, WrapMode,
+};
+
+/// Seconds a thrown rock flies before dropping, about ten cells at the
+/// default action rate.
+const PROJECTILE_SECONDS: f32 = 0.5;
+
+/// A thrown rock, moving a cell along `direction` every action tick until it
+/// hits something or `timer` runs out.
+pub(crate) struct Projectile {
+    direction: Direction,
+    timer: Timer,
+}
+
+/// Stuns an enemy when commands are applied. A boulder pushed or a trap sprung
+/// earlier in the same tick may have crushed it by then, so a despawned enemy
+/// is skipped.
+pub(crate) struct StunIfAlive(pub(crate) Entity);
+
+impl Command for StunIfAlive {
+    fn write(self: Box<Self>, world: &mut World) {
+        if let Some(mut enemy) = world.get_entity_mut(self.0) {
+            enemy.insert(Stunned::new());
+        }
+    }
+}
+
+/// Stuns the first enemy among `entities`, returning whether there was one.
+fn stun_enemy_in(
+    commands: &mut Commands,
+    entities: &[Entity],
+    enemies: &Query<(), With<Enemy>>,
+) -> bool {
+    match entities.iter().find(|e| enemies.get(**e).is_ok()) {
+        Some(enemy) => {
+            commands.add(StunIfAlive(*enemy));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Throws a carried rock at the faced cell. Nothing is thrown into a wall or
+/// past the arena edge, and the rock is kept for another try.
+pub(crate) fn player_throw_action(
+    mut commands: Commands,
+    practice: Res<PracticeMode>,
+    materials: Res<Materials>,
+    arena: Res<ArenaConfig>,
+    wrap: Res<WrapMode>,
+    grid: Res<WorldGrid>,
+    enemies: Query<(), With<Enemy>>,
+    mut players: Query<(&Position, &mut Player)>,
+) {
+    for (pos, mut player) in players.iter_mut() {
+        if !player.throwing {
+            continue;
+        }
+        player.throwing = false;
+        let target = arena.step(pos, player.face_direction, &wrap);
+        if (player.rocks == 0 && !practice.0)
+            || !arena.contains(&target)
+            || first_wall_at(&grid, &target).is_some()
+        {
+            continue;
+        }
+        if !practice.0 {
+            player.rocks -= 1;
+        }
+        // Point blank hits land straight away
+        if stun_enemy_in(&mut commands, &entities_at(&grid, &target), &enemies) {
+            continue;
+        }
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: materials.rock_material.clone(),
+                sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+                ..Default::default()
+            })
+            .insert(Projectile {
+                direction: player.face_direction,
+                timer: Timer::from_seconds(PROJECTILE_SECONDS, false),
+            })
+            .insert(target)
+            .insert(Size::square(0.25));
+    }
+}
+
+/// Runs before the throw each tick, so a new rock waits a tick before moving.
+pub(crate) fn move_projectiles(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    wrap: Res<WrapMode>,
+    timesteps: Res<Timesteps>,
+    grid: Res<WorldGrid>,
+    enemies: Query<(), With<Enemy>>,
+    mut projectiles: Query<(Entity, &mut Projectile, &mut Position)>,
+) {
+    let step = std::time::Duration::from_secs_f64(timesteps.action_step());
+    for (e, mut projectile, mut pos) in projectiles.iter_mut() {
+        // An enemy may have walked into the rock since last tick
+        if stun_enemy_in(&mut commands, &entities_at(&grid, &pos), &enemies) {
+            commands.entity(e).despawn();
+            continue;
+        }
+        let next = arena.step(&pos, projectile.direction, &wrap);
+        let spent = projectile.timer.tick(step).finished();
+        if spent || !arena.contains(&next) || first_wall_at(&grid, &next).is_some() {
+            commands.entity(e).despawn();
+            continue;
+        }
+        if stun_enemy_in(&mut commands, &entities_at(&grid, &next), &enemies) {
+            commands.entity(e).despawn();
+            continue;
+        }
+        *pos = next;
+    }
+}
@@ -0,0 +1,549 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::boulder::{spawn_boulder, Boulder};
+use crate::enemy::{spawn_enemy, Enemy, EnemyHealth, EnemyKind};
+use crate::gem::{spawn_gem, Gem};
+use crate::trap::{spawn_trap, Trap, TrapEffect};
+use crate::{
+    insert_treasure, Action, Boundary, Digging, Direction, GameState, GameTimer, Lives, Materials,
+    PlayTime, Player, PlayerId, PlayerPath, PlayerScore, Position, Score, Size, Toughness,
+    Treasure, TreasureWalls, Wall, WallType,
+};
+
+/// One `<slot>.json` per save, next to the game.
+const SAVES_DIR: &str = "saves";
+
+/// Bumped whenever `GameSnapshot` or `SlotHeader` changes shape, so older
+/// saves are turned away instead of being misread.
+const SAVE_VERSION: u32 = 7;
+
+/// Saves over the slot the game was last saved to or loaded from, or to a new
+/// one; with Shift, always to a new one.
+const SAVE_KEY: KeyCode = KeyCode::F5;
+/// Goes back to the slot the game was last saved to or loaded from.
+const LOAD_KEY: KeyCode = KeyCode::F9;
+
+#[derive(Serialize, Deserialize)]
+struct SavedWall {
+    position: Position,
+    boundary: bool,
+    /// Walls without a type dig like dirt.
+    kind: Option<WallType>,
+    hits_remaining: Option<u32>,
+    treasure: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPlayer {
+    id: u8,
+    position: Position,
+    face_direction: Direction,
+    rocks: u32,
+    score: PlayerScore,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEnemy {
+    kind: EnemyKind,
+    position: Position,
+    hit_points: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTrap {
+    position: Position,
+    armed: bool,
+    effect: TrapEffect,
+}
+
+/// Everything needed to pick a game back up: the walls, the players, what moves,
+/// gets picked up or was laid around them, and the score and clocks. Portals, air and
+/// the exit stay put for the whole game, so they're left as they are.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct GameSnapshot {
+    walls: Vec<SavedWall>,
+    players: Vec<SavedPlayer>,
+    enemies: Vec<SavedEnemy>,
+    gems: Vec<Position>,
+    boulders: Vec<Position>,
+    traps: Vec<SavedTrap>,
+    dug: u32,
+    built: u32,
+    collected: u32,
+    points: u32,
+    lives: u8,
+    play_time: f32,
+    /// Seconds gone on the clock of a timed game.
+    timer_elapsed: Option<f32>,
+}
+
+/// The first line of a save: what the load list shows, and the version,
+/// checked before the rest is read.
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub(crate) struct SlotHeader {
+    version: u32,
+    /// `dug + built`, as the high score counts it.
+    pub(crate) score: u32,
+    /// Seconds since the Unix epoch.
+    pub(crate) saved_at: u64,
+}
+
+impl SlotHeader {
+    fn new(snapshot: &GameSnapshot) -> Self {
+        Self {
+            version: SAVE_VERSION,
+            score: snapshot.dug + snapshot.built,
+            saved_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+        }
+    }
+
+    fn parse(line: &str) -> Result<Self, String> {
+        let header = serde_json::from_str::<Self>(line).map_err(|e| e.to_string())?;
+        if header.version != SAVE_VERSION {
+            return Err(format!(
+                "saved by version {}, expected {}",
+                header.version, SAVE_VERSION
+            ));
+        }
+        Ok(header)
+    }
+}
+
+pub(crate) struct SaveSlot {
+    /// The file name, less `.json`.
+    pub(crate) name: String,
+    /// Why the slot can't be loaded, for a corrupt or outdated save.
+    pub(crate) header: Result<SlotHeader, String>,
+}
+
+/// The saves on disk, newest first as of the last `scan`, and which one the
+/// game under way belongs to.
+pub(crate) struct SaveSlots {
+    dir: PathBuf,
+    pub(crate) slots: Vec<SaveSlot>,
+    /// Last saved to or loaded from, so F5 and F9 keep to one slot.
+    current: Option<String>,
+}
+
+impl Default for SaveSlots {
+    fn default() -> Self {
+        Self::new(SAVES_DIR)
+    }
+}
+
+impl SaveSlots {
+    pub(crate) fn new(dir: impl Into<PathBuf>) -> Self {
+        let mut slots = Self {
+            dir: dir.into(),
+            slots: Vec::new(),
+            current: None,
+        };
+        slots.scan();
+        slots
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// Rereads every slot's header, leaving the rest of each save unread. A
+    /// missing directory just means no saves yet.
+    pub(crate) fn scan(&mut self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => {
+                self.slots.clear();
+                return;
+            }
+        };
+        let mut slots: Vec<SaveSlot> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+            .filter_map(|path| {
+                let name = path.file_stem()?.to_str()?.to_string();
+                let header = fs::File::open(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|file| {
+                        let mut line = String::new();
+                        BufReader::new(file)
+                            .read_line(&mut line)
+                            .map_err(|e| e.to_string())?;
+                        SlotHeader::parse(&line)
+                    });
+                Some(SaveSlot { name, header })
+            })
+            .collect();
+        slots.sort_by(|a, b| {
+            let saved_at = |slot: &SaveSlot| slot.header.as_ref().map_or(0, |h| h.saved_at);
+            saved_at(b)
+                .cmp(&saved_at(a))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        self.slots = slots;
+    }
+
+    /// Saves to a slot of its own, named after the first free number.
+    pub(crate) fn create(&mut self, snapshot: &GameSnapshot) -> Result<String, String> {
+        let name = (1..)
+            .map(|n| format!("slot-{}", n))
+            .find(|name| !self.path(name).exists())
+            .unwrap();
+        self.overwrite(&name, snapshot)?;
+        Ok(name)
+    }
+
+    pub(crate) fn overwrite(&mut self, name: &str, snapshot: &GameSnapshot) -> Result<(), String> {
+        let header =
+            serde_json::to_string(&SlotHeader::new(snapshot)).map_err(|e| e.to_string())?;
+        let body = serde_json::to_string(snapshot).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        fs::write(self.path(name), format!("{}\n{}", header, body)).map_err(|e| e.to_string())?;
+        self.current = Some(name.to_string());
+        self.scan();
+        Ok(())
+    }
+
+    pub(crate) fn delete(&mut self, name: &str) -> Result<(), String> {
+        fs::remove_file(self.path(name)).map_err(|e| e.to_string())?;
+        if self.current.as_deref() == Some(name) {
+            self.current = None;
+        }
+        self.scan();
+        Ok(())
+    }
+
+    pub(crate) fn load(&mut self, name: &str) -> Result<GameSnapshot, String> {
+        let contents = fs::read_to_string(self.path(name)).map_err(|e| e.to_string())?;
+        let (header, body) = contents
+            .split_once('\n')
+            .ok_or_else(|| "no header line".to_string())?;
+        SlotHeader::parse(header)?;
+        let snapshot = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        self.current = Some(name.to_string());
+        Ok(snapshot)
+    }
+}
+
+/// Read from a slot, and put in place once the arena it goes into has
+/// spawned.
+#[derive(Default)]
+pub(crate) struct PendingLoad(pub(crate) Option<GameSnapshot>);
+
+fn save_game(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut slots: ResMut<SaveSlots>,
+    score: Res<Score>,
+    lives: Res<Lives>,
+    play_time: Res<PlayTime>,
+    timer: Option<Res<GameTimer>>,
+    walls: Query<
+        (
+            &Position,
+            Option<&WallType>,
+            Option<&Toughness>,
+            Option<&Boundary>,
+            Option<&Digging>,
+            Option<&Treasure>,
+        ),
+        With<Wall>,
+    >,
+    players: Query<(&PlayerId, &Position, &Player)>,
+    enemies: Query<(&Enemy, &Position, &EnemyHealth)>,
+    gems: Query<&Position, With<Gem>>,
+    boulders: Query<&Position, With<Boulder>>,
+    traps: Query<(&Trap, &Position)>,
+) {
+    if !keyboard_input.just_pressed(SAVE_KEY) {
+        return;
+    }
+    let snapshot = GameSnapshot {
+        walls: walls
+            .iter()
+            .map(
+                |(position, kind, toughness, boundary, digging, treasure)| SavedWall {
+                    position: *position,
+                    boundary: boundary.is_some(),
+                    kind: kind.copied(),
+                    // A wall still crumbling away is saved whole
+                    hits_remaining: match digging {
+                        Some(_) => kind.map(|kind| kind.hits()),
+                        None => toughness.map(|t| t.hits_remaining),
+                    },
+                    treasure: treasure.map(|t| t.0),
+                },
+            )
+            .collect(),
+        players: players
+            .iter()
+            .map(|(id, position, player)| SavedPlayer {
+                id: id.0,
+                position: *position,
+                face_direction: player.face_direction,
+                rocks: player.rocks,
+                score: score.players.get(id).copied().unwrap_or_default(),
+            })
+            .collect(),
+        enemies: enemies
+            .iter()
+            .map(|(enemy, position, health)| SavedEnemy {
+                kind: enemy.kind,
+                position: *position,
+                hit_points: health.0,
+            })
+            .collect(),
+        gems: gems.iter().copied().collect(),
+        boulders: boulders.iter().copied().collect(),
+        traps: traps
+            .iter()
+            .map(|(trap, position)| SavedTrap {
+                position: *position,
+                armed: trap.armed,
+                effect: trap.effect,
+            })
+            .collect(),
+        dug: score.dug,
+        built: score.built,
+        collected: score.gems,
+        points: score.points,
+        lives: lives.0,
+        play_time: play_time.0,
+        timer_elapsed: timer.map(|timer| timer.0.elapsed_secs()),
+    };
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    let saved = match slots.current.clone() {
+        Some(name) if !shift => slots.overwrite(&name, &snapshot).map(|()| name),
+        _ => slots.create(&snapshot),
+    };
+    match saved {
+        Ok(name) => info!("Saved game to slot {}", name),
+        Err(e) => warn!("Could not save game: {}", e),
+    }
+}
+
+fn load_game(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut slots: ResMut<SaveSlots>,
+    mut pending: ResMut<PendingLoad>,
+) {
+    if !keyboard_input.just_pressed(LOAD_KEY) {
+        return;
+    }
+    let name = match slots.current.clone() {
+        Some(name) => name,
+        None => {
+            warn!("Nothing saved this game to load");
+            return;
+        }
+    };
+    match slots.load(&name) {
+        Ok(snapshot) => pending.0 = Some(snapshot),
+        Err(e) => warn!("Could not load slot {}: {}", name, e),
+    }
+}
+
+/// A new game, or the end of one, doesn't carry on the last one's slot.
+fn forget_slot(mut slots: ResMut<SaveSlots>) {
+    slots.current = None;
+}
+
+/// Replaces the walls, enemies, gems, boulders and traps with the saved ones and puts
+/// the players back where they were. Runs after `Update`, so a game loaded from
+/// the menu has had its arena spawned to replace.
+fn apply_pending_load(
+    mut commands: Commands,
+    mut pending: ResMut<PendingLoad>,
+    materials: Res<Materials>,
+    treasure: Res<TreasureWalls>,
+    mut score: ResMut<Score>,
+    mut lives: ResMut<Lives>,
+    mut play_time: ResMut<PlayTime>,
+    timer: Option<ResMut<GameTimer>>,
+    saved_pieces: Query<
+        Entity,
+        Or<(
+            With<Wall>,
+            With<Enemy>,
+            With<Gem>,
+            With<Boulder>,
+            With<Trap>,
+        )>,
+    >,
+    mut players: Query<(&PlayerId, &mut Position, &mut Player, &mut PlayerPath)>,
+) {
+    let snapshot = match pending.0.take() {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+    for e in saved_pieces.iter() {
+        // Takes any crack overlay with it
+        commands.entity(e).despawn_recursive();
+    }
+    for saved in &snapshot.enemies {
+        spawn_enemy(
+            &mut commands,
+            &materials,
+            saved.kind,
+            saved.position,
+            saved.hit_points,
+        );
+    }
+    for position in &snapshot.gems {
+        spawn_gem(&mut commands, &materials, *position);
+    }
+    for position in &snapshot.boulders {
+        spawn_boulder(&mut commands, &materials, *position);
+    }
+    for saved in &snapshot.traps {
+        let trap = Trap {
+            armed: saved.armed,
+            effect: saved.effect,
+        };
+        spawn_trap(&mut commands, &materials, saved.position, trap);
+    }
+    for saved in &snapshot.walls {
+        let material = if saved.boundary {
+            materials.boundary_material.clone()
+        } else {
+            saved.kind.map_or(materials.wall_material.clone(), |kind| {
+                materials.wall_for(kind)
+            })
+        };
+        let mut wall = commands.spawn_bundle(SpriteBundle {
+            material,
+            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+            ..Default::default()
+        });
+        wall.insert(Wall)
+            .insert(saved.position)
+            .insert(Size::square(0.8));
+        if saved.boundary {
+            wall.insert(Boundary);
+        }
+        if let Some(kind) = saved.kind {
+            wall.insert(kind);
+        }
+        if let Some(hits_remaining) = saved.hits_remaining {
+            wall.insert(Toughness { hits_remaining });
+        }
+        if let Some(bonus) = saved.treasure {
+            insert_treasure(&mut wall, &materials, &treasure, bonus);
+        }
+    }
+    score.dug = snapshot.dug;
+    score.built = snapshot.built;
+    score.gems = snapshot.collected;
+    score.points = snapshot.points;
+    score.players.clear();
+    for (id, mut position, mut player, mut path) in players.iter_mut() {
+        let saved = match snapshot.players.iter().find(|saved| saved.id == id.0) {
+            Some(saved) => saved,
+            None => {
+                warn!("Save has no player {}, leaving them in place", id.0);
+                continue;
+            }
+        };
+        *position = saved.position;
+        player.face_direction = saved.face_direction;
+        player.rocks = saved.rocks;
+        player.action = Action::Idle;
+        path.0.clear();
+        score.players.insert(*id, saved.score);
+    }
+    lives.0 = snapshot.lives;
+    play_time.0 = snapshot.play_time;
+    if let (Some(mut timer), Some(elapsed)) = (timer, snapshot.timer_elapsed) {
+        timer
+            .0
+            .set_elapsed(std::time::Duration::from_secs_f32(elapsed));
+    }
+}
+
+pub(crate) struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<SaveSlots>()
+            .init_resource::<PendingLoad>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(save_game.system())
+                    .with_system(load_game.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Playing).with_system(forget_slot.system()),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, apply_pending_load.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(dug: u32) -> GameSnapshot {
+        GameSnapshot {
+            walls: Vec::new(),
+            players: Vec::new(),
+            enemies: Vec::new(),
+            gems: Vec::new(),
+            boulders: Vec::new(),
+            traps: Vec::new(),
+            dug,
+            built: 2,
+            collected: 0,
+            points: 0,
+            lives: 3,
+            play_time: 0.,
+            timer_elapsed: None,
+        }
+    }
+
+    #[test]
+    fn slots_are_created_overwritten_loaded_and_deleted() {
+        let dir = std::env::temp_dir().join(format!("carnival-saves-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut slots = SaveSlots::new(&dir);
+        assert!(slots.slots.is_empty());
+
+        let first = slots.create(&snapshot(1)).unwrap();
+        let second = slots.create(&snapshot(5)).unwrap();
+        assert_ne!(first, second);
+        slots.overwrite(&first, &snapshot(10)).unwrap();
+        let header = |slots: &SaveSlots, name: &str| {
+            slots
+                .slots
+                .iter()
+                .find(|slot| slot.name == name)
+                .map(|slot| slot.header.clone())
+        };
+        assert_eq!(header(&slots, &first).unwrap().unwrap().score, 12);
+        assert_eq!(slots.load(&second).unwrap().dug, 5);
+
+        // Turned away, not panicked over
+        fs::write(dir.join("corrupt.json"), "not a save").unwrap();
+        let outdated = format!(
+            "{{\"version\":{},\"score\":0,\"saved_at\":0}}\n{{}}",
+            SAVE_VERSION - 1
+        );
+        fs::write(dir.join("outdated.json"), outdated).unwrap();
+        slots.scan();
+        assert_eq!(slots.slots.len(), 4);
+        for name in ["corrupt", "outdated"].iter() {
+            assert!(header(&slots, name).unwrap().is_err());
+            assert!(slots.load(name).is_err());
+        }
+
+        slots.delete(&first).unwrap();
+        assert!(header(&slots, &first).is_none());
+        assert!(slots.delete(&first).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::difficulty::{Difficulty, DifficultyBase};
+use crate::level::Level;
+use crate::{ArenaConfig, GameRng, GameState, PlayerSpawn, MIN_ARENA_SIDE};
+
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+/// Enough base62 digits for the 13 bytes a code packs. Shorter numbers are
+/// padded with zeros, so every code is the same length.
+const CODE_DIGITS: usize = 18;
+const PAYLOAD_BITS: u32 = 96;
+
+/// Everything that decides how a generated arena comes out, so another player
+/// can replay it from a short code. Hand-made levels can't be shared this way.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct ShareConfig {
+    pub seed: u64,
+    pub width: u8,
+    pub height: u8,
+    pub difficulty: Difficulty,
+    /// The configured wall density, before the difficulty scales it, in
+    /// percent.
+    pub wall_density: u8,
+}
+
+impl ShareConfig {
+    /// `None` for an arena too big for a code to hold.
+    pub(crate) fn current(
+        seed: &GameSeed,
+        arena: &ArenaConfig,
+        difficulty: Difficulty,
+        base: &DifficultyBase,
+    ) -> Option<Self> {
+        use std::convert::TryFrom;
+        Some(Self {
+            seed: seed.0,
+            width: u8::try_from(arena.width).ok()?,
+            height: u8::try_from(arena.height).ok()?,
+            difficulty,
+            wall_density: (base.wall_density * 100.0).round().clamp(0.0, 100.0) as u8,
+        })
+    }
+
+    fn validate(self) -> Result<Self, String> {
+        for (side, value) in [("width", self.width), ("height", self.height)].iter() {
+            if (*value as u32) < MIN_ARENA_SIDE {
+                return Err(format!(
+                    "arena {} {} is below the minimum of {}",
+                    side, value, MIN_ARENA_SIDE
+                ));
+            }
+        }
+        if self.wall_density > 100 {
+            return Err(format!("wall density {}% is over 100%", self.wall_density));
+        }
+        Ok(self)
+    }
+}
+
+fn difficulty_index(difficulty: Difficulty) -> u8 {
+    match difficulty {
+        Difficulty::Easy => 0,
+        Difficulty::Normal => 1,
+        Difficulty::Hard => 2,
+    }
+}
+
+fn difficulty_from_index(index: u8) -> Option<Difficulty> {
+    match index {
+        0 => Some(Difficulty::Easy),
+        1 => Some(Difficulty::Normal),
+        2 => Some(Difficulty::Hard),
+        _ => None,
+    }
+}
+
+/// Catches most mistyped codes, which would otherwise decode to some other
+/// valid arena.
+fn checksum(payload: u128) -> u8 {
+    payload
+        .to_le_bytes()
+        .iter()
+        .take(PAYLOAD_BITS as usize / 8)
+        .fold(0u8, |sum, byte| sum.wrapping_mul(31).wrapping_add(*byte))
+}
+
+pub fn encode_share_code(config: &ShareConfig) -> String {
+    let payload = config.seed as u128
+        | (config.width as u128) << 64
+        | (config.height as u128) << 72
+        | (difficulty_index(config.difficulty) as u128) << 80
+        | (config.wall_density as u128) << 88;
+    let mut value = payload | (checksum(payload) as u128) << PAYLOAD_BITS;
+    let mut digits = [ALPHABET[0]; CODE_DIGITS];
+    for digit in digits.iter_mut().rev() {
+        *digit = ALPHABET[(value % 62) as usize];
+        value /= 62;
+    }
+    String::from_utf8(digits.to_vec()).unwrap()
+}
+
+/// Surrounding whitespace is ignored; anything else out of place is an error
+/// saying what's wrong with the code.
+pub fn decode_share_code(code: &str) -> Result<ShareConfig, String> {
+    let code = code.trim();
+    if code.chars().count() != CODE_DIGITS {
+        return Err(format!(
+            "expected {} characters, got {}",
+            CODE_DIGITS,
+            code.chars().count()
+        ));
+    }
+    let mut value: u128 = 0;
+    for c in code.chars() {
+        let digit = ALPHABET
+            .iter()
+            .position(|a| *a as char == c)
+            .ok_or_else(|| format!("unexpected character {:?}", c))?;
+        value = value * 62 + digit as u128;
+    }
+    if value >> (PAYLOAD_BITS + 8) != 0 {
+        return Err("code is out of range".to_string());
+    }
+    let payload = value & ((1 << PAYLOAD_BITS) - 1);
+    if (value >> PAYLOAD_BITS) as u8 != checksum(payload) {
+        return Err("checksum doesn't match".to_string());
+    }
+    let byte = |shift: u32| (payload >> shift) as u8;
+    let difficulty = difficulty_from_index(byte(80))
+        .ok_or_else(|| format!("unknown difficulty {}", byte(80)))?;
+    ShareConfig {
+        seed: payload as u64,
+        width: byte(64),
+        height: byte(72),
+        difficulty,
+        wall_density: byte(88),
+    }
+    .validate()
+}
+
+/// Seeds the `GameRng` at the start of each game, so a generated arena can be
+/// replayed from its seed. Drawn from the `GameRng` itself between games, so a
+/// `CARNIVAL_SEED` still repeats a whole run.
+pub(crate) struct GameSeed(pub(crate) u64);
+
+impl GameSeed {
+    pub(crate) fn roll(rng: &mut GameRng) -> Self {
+        Self(rng.0.gen())
+    }
+}
+
+/// The code for the game under way, or the next one from the menu. `None`
+/// for hand-made levels and arenas too big to share.
+#[derive(Default)]
+pub(crate) struct ShareCode(pub(crate) Option<String>);
+
+fn reseed_rng(seed: Res<GameSeed>, mut rng: ResMut<GameRng>) {
+    *rng = GameRng::new(Some(seed.0));
+}
+
+fn roll_seed(mut seed: ResMut<GameSeed>, mut rng: ResMut<GameRng>) {
+    *seed = GameSeed::roll(&mut rng);
+}
+
+/// Sent with a code entered from the menu, to play it next.
+pub(crate) struct ImportShareCode(pub(crate) ShareConfig);
+
+fn apply_share_code(
+    mut imports: EventReader<ImportShareCode>,
+    level: Option<Res<Level>>,
+    mut seed: ResMut<GameSeed>,
+    mut arena: ResMut<ArenaConfig>,
+    mut spawn: ResMut<PlayerSpawn>,
+    mut difficulty: ResMut<Difficulty>,
+    mut base: ResMut<DifficultyBase>,
+) {
+    for ImportShareCode(config) in imports.iter() {
+        if level.is_some() {
+            warn!("Ignoring share code, since a hand-made level is loaded");
+            continue;
+        }
+        seed.0 = config.seed;
+        *arena = ArenaConfig {
+            width: config.width as u32,
+            height: config.height as u32,
+        };
+        *spawn = spawn.validated(&arena, None);
+        *difficulty = config.difficulty;
+        base.wall_density = config.wall_density as f32 / 100.0;
+    }
+}
+
+fn update_share_code(
+    seed: Res<GameSeed>,
+    arena: Res<ArenaConfig>,
+    difficulty: Res<Difficulty>,
+    base: Res<DifficultyBase>,
+    level: Option<Res<Level>>,
+    mut code: ResMut<ShareCode>,
+) {
+    let changed =
+        seed.is_changed() || arena.is_changed() || difficulty.is_changed() || base.is_changed();
+    if !changed {
+        return;
+    }
+    code.0 = match level {
+        Some(_) => None,
+        None => ShareConfig::current(&seed, &arena, *difficulty, &base)
+            .map(|config| encode_share_code(&config)),
+    };
+}
+
+pub(crate) struct SharePlugin;
+
+impl Plugin for SharePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ShareCode>()
+            .add_event::<ImportShareCode>()
+            .add_system(apply_share_code.system().label("import"))
+            .add_system(update_share_code.system().after("import"))
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing)
+                    .with_system(reseed_rng.system().before("boundaries")),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(roll_seed.system()))
+            .add_system_set(SystemSet::on_exit(GameState::Win).with_system(roll_seed.system()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ShareConfig {
+        ShareConfig {
+            seed: 0x0123_4567_89ab_cdef,
+            width: 20,
+            height: 12,
+            difficulty: Difficulty::Hard,
+            wall_density: 40,
+        }
+    }
+
+    #[test]
+    fn codes_round_trip() {
+        let extremes = ShareConfig {
+            seed: u64::MAX,
+            width: u8::MAX,
+            height: MIN_ARENA_SIDE as u8,
+            difficulty: Difficulty::Easy,
+            wall_density: 0,
+        };
+        let zero_seed = ShareConfig {
+            seed: 0,
+            ..config()
+        };
+        for config in [config(), extremes, zero_seed].iter() {
+            let code = encode_share_code(config);
+            assert_eq!(code.len(), CODE_DIGITS);
+            assert!(code.chars().all(|c| c.is_ascii_alphanumeric()));
+            assert_eq!(decode_share_code(&code), Ok(*config));
+        }
+        let padded = format!("  {}\n", encode_share_code(&config()));
+        assert_eq!(decode_share_code(&padded), Ok(config()));
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        let code = encode_share_code(&config());
+        assert!(decode_share_code("").is_err());
+        assert!(decode_share_code(&code[1..]).is_err());
+        assert!(decode_share_code(&format!("{}0", code)).is_err());
+        assert!(decode_share_code(&code.replacen(&code[..1], "-", 1)).is_err());
+        assert!(decode_share_code(&"z".repeat(CODE_DIGITS)).is_err());
+
+        // Mistyping any one character of this code is caught
+        for index in 0..CODE_DIGITS {
+            let mut typo = code.clone().into_bytes();
+            typo[index] = if typo[index] == b'7' { b'8' } else { b'7' };
+            let typo = String::from_utf8(typo).unwrap();
+            assert!(decode_share_code(&typo).is_err(), "{} decoded", typo);
+        }
+    }
+
+    #[test]
+    fn out_of_range_configs_are_rejected() {
+        let narrow = ShareConfig {
+            width: 2,
+            ..config()
+        };
+        assert!(decode_share_code(&encode_share_code(&narrow)).is_err());
+        let dense = ShareConfig {
+            wall_density: 101,
+            ..config()
+        };
+        assert!(decode_share_code(&encode_share_code(&dense)).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::enemy::Enemy;
+use crate::grid::{entities_at, first_wall_at, WorldGrid};
+use crate::projectile::StunIfAlive;
+use crate::{
+    ArenaConfig, GameState, Materials, Paused, Player, Position, PracticeMode, Size, WorldSteps,
+    WrapMode,
+};
+
+/// What a trap does to the enemy that springs it. Enemies have no hit points,
+/// so the damaging kind crushes outright, like a pushed boulder.
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrapEffect {
+    Stun,
+    Crush,
+}
+
+impl Default for TrapEffect {
+    fn default() -> Self {
+        TrapEffect::Stun
+    }
+}
+
+/// Laid by a player for a rock. Enemies path straight over traps, so the first
+/// to stand on an armed one springs it and the trap is used up. A trap laid
+/// under an enemy stays unarmed until that cell is clear.
+pub(crate) struct Trap {
+    pub(crate) armed: bool,
+    pub(crate) effect: TrapEffect,
+}
+
+pub(crate) fn spawn_trap(
+    commands: &mut Commands,
+    materials: &Materials,
+    position: Position,
+    trap: Trap,
+) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.trap_material.clone(),
+            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+            ..Default::default()
+        })
+        .insert(trap)
+        .insert(position)
+        .insert(Size::square(0.5));
+}
+
+/// Lays a trap in the faced cell for a carried rock, alongside building. Only
+/// open ground, or ground an enemy stands on, takes one; otherwise the rock is
+/// kept for another try.
+pub(crate) fn player_trap_action(
+    mut commands: Commands,
+    practice: Res<PracticeMode>,
+    effect: Res<TrapEffect>,
+    materials: Res<Materials>,
+    arena: Res<ArenaConfig>,
+    wrap: Res<WrapMode>,
+    grid: Res<WorldGrid>,
+    enemies: Query<(), With<Enemy>>,
+    mut players: Query<(&Position, &mut Player)>,
+) {
+    let mut laid: Vec<Position> = Vec::new();
+    for (pos, mut player) in players.iter_mut() {
+        if !player.trapping {
+            continue;
+        }
+        player.trapping = false;
+        let target = arena.step(pos, player.face_direction, &wrap);
+        let open = entities_at(&grid, &target)
+            .iter()
+            .all(|e| enemies.get(*e).is_ok());
+        if (player.rocks == 0 && !practice.0)
+            || !arena.contains(&target)
+            || first_wall_at(&grid, &target).is_some()
+            || !open
+            || laid.contains(&target)
+        {
+            continue;
+        }
+        if !practice.0 {
+            player.rocks -= 1;
+        }
+        laid.push(target);
+        let trap = Trap {
+            armed: false,
+            effect: *effect,
+        };
+        spawn_trap(&mut commands, &materials, target, trap);
+    }
+}
+
+/// Checked every frame, since enemies step on their own clock.
+fn trap_trigger(
+    mut commands: Commands,
+    paused: Res<Paused>,
+    mut traps: Query<(Entity, &mut Trap, &Position)>,
+    enemies: Query<(Entity, &Position), With<Enemy>>,
+) {
+    if paused.0 {
+        return;
+    }
+    for (e, mut trap, pos) in traps.iter_mut() {
+        let caught = enemies.iter().find(|(_, enemy)| *enemy == pos);
+        match (trap.armed, caught) {
+            (false, None) => trap.armed = true,
+            (true, Some((enemy, _))) => {
+                match trap.effect {
+                    TrapEffect::Stun => commands.add(StunIfAlive(enemy)),
+                    TrapEffect::Crush => commands.entity(enemy).despawn(),
+                }
+                commands.entity(e).despawn();
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) struct TrapPlugin;
+
+impl Plugin for TrapPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(
+                trap_trigger
+                    .system()
+                    .label(WorldSteps::Traps)
+                    .after(WorldSteps::Contact),
+            ),
+        );
+    }
+}
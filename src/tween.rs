@@ -0,0 +1,206 @@
+use bevy::prelude::*;
+
+use crate::{Paused, TimeScale};
+
+/// Shapes how a tween moves from its start to its end value.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub(crate) enum Easing {
+    Linear,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps linear progress in `0..=1` onto the eased curve.
+    pub(crate) fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseOut => 1. - (1. - t) * (1. - t),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    2. * t * t
+                } else {
+                    1. - (-2. * t + 2.).powi(2) / 2.
+                }
+            }
+        }
+    }
+}
+
+pub(crate) trait Lerp: Copy {
+    fn lerp(self, to: Self, t: f32) -> Self;
+}
+
+fn mix(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+impl Lerp for f32 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        mix(self, to, t)
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+
+impl Lerp for Color {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Color::rgba(
+            mix(self.r(), to.r(), t),
+            mix(self.g(), to.g(), t),
+            mix(self.b(), to.b(), t),
+            mix(self.a(), to.a(), t),
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Tween<T: Lerp> {
+    pub from: T,
+    pub to: T,
+    pub duration: f32,
+    pub easing: Easing,
+    pub elapsed: f32,
+}
+
+impl<T: Lerp> Tween<T> {
+    pub(crate) fn new(from: T, to: T, duration: f32, easing: Easing) -> Self {
+        Self {
+            from,
+            to,
+            duration,
+            easing,
+            elapsed: 0.,
+        }
+    }
+
+    /// Moves the tween forward by `delta` seconds and returns the current value.
+    pub(crate) fn advance(&mut self, delta: f32) -> T {
+        self.elapsed = (self.elapsed + delta).min(self.duration);
+        self.value()
+    }
+
+    pub(crate) fn value(&self) -> T {
+        let progress = if self.duration > 0. {
+            self.elapsed / self.duration
+        } else {
+            1.
+        };
+        self.from.lerp(self.to, self.easing.apply(progress))
+    }
+
+    pub(crate) fn finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+}
+
+/// Animates `Transform::translation`. Entities with a `Position` get snapped back
+/// to the grid in `PostUpdate`, so this is for free-floating sprites.
+pub(crate) struct TranslationTween(pub Tween<Vec3>);
+
+/// Animates `Transform::scale`.
+pub(crate) struct ScaleTween(pub Tween<Vec3>);
+
+/// Animates the colour of the entity's material. Every sprite sharing the
+/// material changes too, so it should belong to this entity alone or to a group
+/// all running the same tween.
+pub(crate) struct ColorTween(pub Tween<Color>);
+
+/// Despawns the entity, children and all, once none of its tweens are left
+/// running.
+pub(crate) struct TweenDespawn;
+
+/// Tweens follow the simulation's clock, so they hold still while paused and
+/// stretch out under slow motion.
+fn tween_delta(time: &Time, scale: &TimeScale, paused: &Paused) -> f32 {
+    if paused.0 {
+        0.
+    } else {
+        scale.delta(time) as f32
+    }
+}
+
+fn tween_translation(
+    mut commands: Commands,
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut q: Query<(Entity, &mut TranslationTween, &mut Transform)>,
+) {
+    let delta = tween_delta(&time, &scale, &paused);
+    for (e, mut tween, mut transform) in q.iter_mut() {
+        transform.translation = tween.0.advance(delta);
+        if tween.0.finished() {
+            commands.entity(e).remove::<TranslationTween>();
+        }
+    }
+}
+
+fn tween_scale(
+    mut commands: Commands,
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut q: Query<(Entity, &mut ScaleTween, &mut Transform)>,
+) {
+    let delta = tween_delta(&time, &scale, &paused);
+    for (e, mut tween, mut transform) in q.iter_mut() {
+        transform.scale = tween.0.advance(delta);
+        if tween.0.finished() {
+            commands.entity(e).remove::<ScaleTween>();
+        }
+    }
+}
+
+fn tween_color(
+    mut commands: Commands,
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut q: Query<(Entity, &mut ColorTween, &Handle<ColorMaterial>)>,
+) {
+    let delta = tween_delta(&time, &scale, &paused);
+    for (e, mut tween, handle) in q.iter_mut() {
+        let color = tween.0.advance(delta);
+        if let Some(material) = materials.get_mut(handle) {
+            material.color = color;
+        }
+        if tween.0.finished() {
+            commands.entity(e).remove::<ColorTween>();
+        }
+    }
+}
+
+fn despawn_tweened(
+    mut commands: Commands,
+    done: Query<
+        Entity,
+        (
+            With<TweenDespawn>,
+            Without<TranslationTween>,
+            Without<ScaleTween>,
+            Without<ColorTween>,
+        ),
+    >,
+) {
+    for e in done.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+pub(crate) struct TweenPlugin;
+
+impl Plugin for TweenPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(tween_translation.system())
+            .add_system(tween_scale.system())
+            .add_system(tween_color.system())
+            .add_system(despawn_tweened.system());
+    }
+}
@@ -0,0 +1,223 @@
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+
+use crate::grid::{entities_at, first_wall_at, WorldGrid};
+use crate::{
+    open_interior_cells, run_if_simulating, ArenaConfig, Boulder, Direction, Footprint, GameRng,
+    GameState, Materials, Paused, Player, Position, Size, SpawnClaims, StepClock, TimeScale,
+    WorldSteps,
+};
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
+/// Sources are kept at least this many steps from a player's start, so nobody
+/// drowns before they've had a chance to wall it off.
+const SOURCE_CLEARANCE: i32 = 4;
+
+/// Flooding variant: springs fill the open ground around them a cell at a
+/// time, following every tunnel that's dug towards them. Water reaching a
+/// player drowns them. Enabled from `config.toml`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WaterMode {
+    pub enabled: bool,
+    pub sources: usize,
+    /// Level of the water at a source. Each cell it spreads to is one lower,
+    /// and water at level 1 goes no further.
+    pub depth: u8,
+    /// Seconds between flow steps.
+    pub step: f64,
+}
+
+impl Default for WaterMode {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sources: 1,
+            depth: 8,
+            step: 0.5,
+        }
+    }
+}
+
+/// Set when the game ended because a player drowned, so the game over screen
+/// can say so.
+#[derive(Default)]
+pub(crate) struct Drowned(pub bool);
+
+pub(crate) struct Water {
+    level: u8,
+}
+
+fn spawn_water_tile(commands: &mut Commands, materials: &Materials, position: Position, level: u8) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.water_material.clone(),
+            sprite: Sprite::new(Vec2::new(20.0, 20.0)),
+            ..Default::default()
+        })
+        .insert(Water { level })
+        .insert(position)
+        .insert(Size::square(1.0));
+}
+
+fn spawn_sources(
+    mut commands: Commands,
+    mode: Res<WaterMode>,
+    mut drowned: ResMut<Drowned>,
+    arena: Res<ArenaConfig>,
+    materials: Res<Materials>,
+    mut rng: ResMut<GameRng>,
+    mut claims: ResMut<SpawnClaims>,
+) {
+    drowned.0 = false;
+    if !mode.enabled {
+        return;
+    }
+    let mut open: Vec<Position> = open_interior_cells(&arena, &claims)
+        .into_iter()
+        .filter(|p| {
+            claims
+                .starts()
+                .iter()
+                .all(|s| (s.x - p.x).abs() + (s.y - p.y).abs() >= SOURCE_CLEARANCE)
+        })
+        .collect();
+    for _ in 0..mode.sources {
+        if open.is_empty() {
+            break;
+        }
+        let index = rng.index(open.len());
+        let position = open.swap_remove(index);
+        claims.claim(position);
+        spawn_water_tile(&mut commands, &materials, position, mode.depth);
+    }
+}
+
+/// Water steps on its own clock, scaled along with everything else.
+fn water_tick(
+    time: Res<Time>,
+    scale: Res<TimeScale>,
+    mode: Res<WaterMode>,
+    mut clock: Local<StepClock>,
+) -> ShouldRun {
+    if !mode.enabled {
+        return ShouldRun::No;
+    }
+    clock.update(scale.delta(&time), mode.step)
+}
+
+/// One cellular-automaton step. Every cell takes the level of its deepest
+/// neighbour less one, so water creeps outwards a cell per step and deepens
+/// shallow pools it joins up with. Walls and boulders hold it back, and a wall
+/// built over water pushes it out.
+fn flow_water(
+    mut commands: Commands,
+    arena: Res<ArenaConfig>,
+    grid: Res<WorldGrid>,
+    materials: Res<Materials>,
+    boulders: Query<(), With<Boulder>>,
+    mut water: Query<(Entity, &mut Water, &Position)>,
+) {
+    let mut cells: HashMap<Position, (Entity, u8)> = HashMap::default();
+    for (e, water, pos) in water.iter_mut() {
+        if first_wall_at(&grid, pos).is_some() {
+            commands.entity(e).despawn();
+        } else {
+            cells.insert(*pos, (e, water.level));
+        }
+    }
+    let blocked = |cell: &Position| {
+        !arena.contains(cell)
+            || first_wall_at(&grid, cell).is_some()
+            || entities_at(&grid, cell)
+                .iter()
+                .any(|e| boulders.get(*e).is_ok())
+    };
+    // Work out the whole step before applying any of it, so water only moves
+    // one cell however the tiles happen to be ordered
+    let mut risen: HashMap<Position, u8> = HashMap::default();
+    for (pos, (_, level)) in cells.iter() {
+        if *level <= 1 {
+            continue;
+        }
+        for direction in DIRECTIONS.iter() {
+            let next = pos.translated(*direction);
+            if blocked(&next) {
+                continue;
+            }
+            let current = cells.get(&next).map_or(0, |(_, level)| *level);
+            let deepest = risen.entry(next).or_insert(current);
+            *deepest = (*deepest).max(level - 1);
+        }
+    }
+    for (pos, level) in risen {
+        match cells.get(&pos) {
+            Some((e, current)) if *current < level => {
+                if let Ok((_, mut water, _)) = water.get_mut(*e) {
+                    water.level = level;
+                }
+            }
+            Some(_) => {}
+            None => spawn_water_tile(&mut commands, &materials, pos, level),
+        }
+    }
+}
+
+/// Checked every frame, since players can walk into water between flow steps.
+fn check_drowning(
+    paused: Res<Paused>,
+    mode: Res<WaterMode>,
+    mut drowned: ResMut<Drowned>,
+    mut state: ResMut<State<GameState>>,
+    water: Query<&Position, With<Water>>,
+    players: Query<(&Position, &Footprint), With<Player>>,
+) {
+    if !mode.enabled || paused.0 {
+        return;
+    }
+    if players
+        .iter()
+        .any(|(player, footprint)| water.iter().any(|w| footprint.covers(player, w)))
+    {
+        drowned.0 = true;
+        state.overwrite_set(GameState::GameOver).unwrap();
+    }
+}
+
+pub(crate) struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Drowned>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing)
+                    .with_system(spawn_sources.system().label("sources").after("air")),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing).with_system(
+                    check_drowning
+                        .system()
+                        .label(WorldSteps::Drowning)
+                        .after(WorldSteps::Gems),
+                ),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(water_tick.system().chain(run_if_simulating.system()))
+                    .with_system(
+                        flow_water
+                            .system()
+                            .label(WorldSteps::Water)
+                            .after(WorldSteps::Enemies),
+                    ),
+            );
+    }
+}